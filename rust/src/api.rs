@@ -1,79 +1,331 @@
-#![allow(dead_code)]
+#![allow(dead_code, clippy::field_reassign_with_default)]
 
-use std::path::PathBuf;
-use anyhow::Result;
-use flutter_rust_bridge::ZeroCopyBuffer;
-use protobuf::descriptor::{MethodDescriptorProto, ServiceDescriptorProto};
-use protobuf::reflect::{FieldDescriptor, FileDescriptor, MessageDescriptor};
+use std::collections::HashSet;
+use std::fmt;
+use std::time::UNIX_EPOCH;
+use anyhow::{anyhow, Context, Result};
+use flutter_rust_bridge::{RustOpaque, StreamSink};
+use protobuf::descriptor::field_descriptor_proto::{Label, Type};
+use protobuf::descriptor::{method_options, FileDescriptorProto, MethodDescriptorProto, ServiceDescriptorProto};
+use protobuf::reflect::{EnumDescriptor, FieldDescriptor, FileDescriptor, MessageDescriptor, OneofDescriptor, Syntax};
+use crate::call;
+use crate::codec;
+use crate::decode;
+use crate::grpc::{self, runtime};
+use crate::json;
+use crate::metadata;
+use crate::pool::{qualified_name, DescriptorPool};
+use crate::reflection::{self, ReflectionClient};
+use crate::schema;
+use crate::source_info;
+use crate::template;
+use crate::text_format;
+use crate::validate;
+use crate::workspace::{load_descriptor_pool, load_files, Loader};
+pub use crate::call::CallSession;
+pub use crate::cancel::CancelToken;
+
+/// Which implementation turns `.proto` sources into descriptors.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ProtoParser {
+    /// Built-in parser, works without any external tool.
+    #[default]
+    Pure,
+    /// Shell out to `protoc`, which must be on `PATH`.
+    Protoc,
+}
+
+/// Where and how `.proto` files are loaded from.
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    /// Directories imports are resolved against, searched in order.
+    ///
+    /// Files outside of every root fall back to their own directory as a root.
+    pub import_roots: Vec<String>,
+    pub parser: ProtoParser,
+}
+
+/// Why a `.proto` file could not be loaded.
+#[derive(Debug, Clone)]
+pub enum ProtoError {
+    FileNotFound {
+        path: String,
+    },
+    /// `line` and `column` are 1-based.
+    Syntax {
+        file: String,
+        line: u32,
+        column: u32,
+        message: String,
+    },
+    UnresolvedImport {
+        file: String,
+        import: String,
+        /// Import roots that were searched, in order.
+        searched: Vec<String>,
+    },
+    /// The file parsed, but refers to types that don't exist, redefines a name, etc.
+    TypeCheck {
+        file: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtoError::FileNotFound { path } => write!(f, "file `{}` not found", path),
+            ProtoError::Syntax { file, line, column, message } => write!(f, "{}:{}:{}: {}", file, line, column, message),
+            ProtoError::UnresolvedImport { file, import, searched } => {
+                write!(f, "import `{}` in `{}` not found, searched: {}", import, file, searched.join(", "))
+            }
+            ProtoError::TypeCheck { file, message } => write!(f, "{}: {}", file, message),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+/// Outcome of loading one of the files passed to [`load_proto_from_files`].
+#[derive(Debug, Clone, Default)]
+pub struct ProtoFileResult {
+    /// The path as passed in, or the import path for files pulled in as dependencies.
+    pub path: String,
+    /// Loaded only because another file imports it.
+    pub imported: bool,
+    pub proto: Option<Proto>,
+    pub error: Option<ProtoError>,
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct Proto {
-    name: String,
-    package: String,
-    services: Vec<Service>,
-    messages: Vec<Message>,
+    pub name: String,
+    pub package: String,
+    pub dependencies: Vec<String>,
+    pub services: Vec<Service>,
+    pub messages: Vec<Message>,
+    pub enums: Vec<Enum>,
+    /// The `syntax` statement, or `package` when there is none.
+    pub location: Option<SourceLocation>,
 }
 
 impl Proto {
-    fn from_file(path: &str) -> Result<Self> {
+    fn from_file(path: &str, workspace: &Workspace) -> Result<Self> {
+        let (pool, names) = load_descriptor_pool(&[path], workspace)?;
+
+        Ok(Proto::from_descriptor(pool.file_by_name(&names[0]).unwrap()))
+    }
+
+    fn from_descriptor(file_descriptor: &FileDescriptor) -> Self {
         let mut proto = Proto::default();
-        let file_descriptor = protobuf_parse::Parser::new()
-            .protoc()
-            .include(PathBuf::from(path).parent().unwrap())
-            .input(path)
-            .parse_and_typecheck()
-            .map(|f| {
-               FileDescriptor::new_dynamic(f.file_descriptors[0].clone(), vec![])
-            })??;
         let file_descriptor_proto = file_descriptor.proto();
         proto.name = file_descriptor_proto.name().to_owned();
         proto.package = file_descriptor_proto.package().to_owned();
-        proto.services = file_descriptor_proto.service.clone().into_iter().map(|s| Service::from_descriptor_proto(s)).collect();
-        proto.messages = file_descriptor.messages().map(|m| Message::from_descriptor_proto(m)).collect();
+        proto.dependencies = file_descriptor_proto.dependency.clone();
+        proto.services = file_descriptor_proto.service.iter().enumerate().map(|(i, s)| Service::from_descriptor_proto(s.clone(), file_descriptor_proto, i)).collect();
+        proto.messages = file_descriptor.messages().map(Message::from_descriptor_proto).collect();
+        proto.enums = file_descriptor.enums().map(|e| Enum::from_descriptor(e, file_descriptor_proto)).collect();
+        proto.location = SourceLocation::find(file_descriptor_proto, &[12]).or_else(|| SourceLocation::find(file_descriptor_proto, &[2]));
 
-        Ok(proto)
+        proto
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Message {
-    name: String,
-    fields: Vec<Field>,
+    pub name: String,
+    pub full_name: String,
+    pub fields: Vec<Field>,
+    pub oneofs: Vec<Oneof>,
+    /// Messages declared inside this one, map entries excluded.
+    pub nested_messages: Vec<Message>,
+    pub enums: Vec<Enum>,
+    pub location: Option<SourceLocation>,
 }
 
 impl Message {
     fn from_descriptor_proto(message_descriptor: MessageDescriptor) -> Self {
         let mut message = Message::default();
         message.name = message_descriptor.name().to_owned();
-        message.fields = message_descriptor.fields().map(|f| Field::from_descriptor(f)).collect();
+        message.full_name = message_descriptor.full_name().to_owned();
+        message.fields = message_descriptor.fields().map(Field::from_descriptor).collect();
+        message.oneofs = message_descriptor.all_oneofs().map(Oneof::from_descriptor).collect();
+        message.nested_messages = message_descriptor
+            .nested_messages()
+            .filter(|m| !m.is_map_entry())
+            .map(Message::from_descriptor_proto)
+            .collect();
+        let file_descriptor_proto = message_descriptor.file_descriptor_proto();
+        message.enums = message_descriptor.nested_enums().map(|e| Enum::from_descriptor(e, file_descriptor_proto)).collect();
+        message.location = source_info::message_path(file_descriptor_proto, &message.full_name).and_then(|p| SourceLocation::find(file_descriptor_proto, &p));
 
         message
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Enum {
+    pub name: String,
+    pub full_name: String,
+    pub values: Vec<EnumValue>,
+    pub deprecated: bool,
+    pub location: Option<SourceLocation>,
+}
+
+impl Enum {
+    fn from_descriptor(enum_descriptor: EnumDescriptor, file_descriptor_proto: &FileDescriptorProto) -> Self {
+        let mut e = Enum::default();
+        e.name = enum_descriptor.name().to_owned();
+        e.full_name = enum_descriptor.full_name().to_owned();
+        e.deprecated = enum_descriptor.proto().options.deprecated();
+        let path = source_info::enum_path(file_descriptor_proto, &e.full_name);
+        e.location = path.as_ref().and_then(|p| SourceLocation::find(file_descriptor_proto, p));
+        e.values = enum_descriptor.values().enumerate().map(|(i, v)| {
+            let mut value = EnumValue::default();
+            value.name = v.name().to_owned();
+            value.number = v.value();
+            value.deprecated = v.proto().options.deprecated();
+            value.aliases = enum_descriptor
+                .values()
+                .filter(|other| other.value() == v.value() && other.name() != v.name())
+                .map(|other| other.name().to_owned())
+                .collect();
+            value.location = path.as_ref().and_then(|p| SourceLocation::find(file_descriptor_proto, &[p, &[2, i as i32][..]].concat()));
+            value
+        }).collect();
+
+        e
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EnumValue {
+    pub name: String,
+    pub number: i32,
+    /// Other names for the same number, declared with `option allow_alias = true`.
+    pub aliases: Vec<String>,
+    pub deprecated: bool,
+    pub location: Option<SourceLocation>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Field {
-    name: String,
-    field_type: FieldKind,
-    optional: bool,
-    repeated: bool,
+    pub name: String,
+    pub number: i32,
+    pub json_name: String,
+    pub field_type: FieldKind,
+    /// Fully-qualified name of the referenced type for `Message`, `Group` and `Enum` fields.
+    pub type_name: Option<String>,
+    /// Explicit proto2 `[default = ...]`, as written in the source.
+    pub default_value: Option<String>,
+    pub label: FieldLabel,
+    pub optional: bool,
+    pub repeated: bool,
+    /// Key and value types when `field_type` is `Map`.
+    pub map: Option<MapEntry>,
+    /// Name of the containing oneof, synthetic proto3 `optional` oneofs included.
+    pub oneof: Option<String>,
+    pub location: Option<SourceLocation>,
 }
 
 impl Field {
     fn from_descriptor(field_descriptor: FieldDescriptor) -> Self {
         let mut field = Field::default();
+        let field_descriptor_proto = field_descriptor.proto();
 
         field.name = field_descriptor.name().to_owned();
-        // FIXME: add type
+        field.number = field_descriptor.number();
+        field.json_name = field_descriptor.json_name().to_owned();
+        field.field_type = FieldKind::from_type(field_descriptor_proto.type_());
+        if field_descriptor_proto.has_type_name() {
+            field.type_name = Some(field_descriptor_proto.type_name().trim_start_matches('.').to_owned());
+        }
+        if field_descriptor_proto.has_default_value() {
+            field.default_value = Some(field_descriptor_proto.default_value().to_owned());
+        }
+        field.label = match field_descriptor_proto.label() {
+            Label::LABEL_REPEATED => FieldLabel::Repeated,
+            Label::LABEL_REQUIRED => FieldLabel::Required,
+            Label::LABEL_OPTIONAL if field_descriptor_proto.proto3_optional() => FieldLabel::Proto3Optional,
+            Label::LABEL_OPTIONAL if field_descriptor.containing_message().file_descriptor().syntax() == Syntax::Proto2 => FieldLabel::Optional,
+            Label::LABEL_OPTIONAL => FieldLabel::Implicit,
+        };
         field.optional = field_descriptor.is_singular();
         field.repeated = field_descriptor.is_repeated();
+        if field_descriptor.is_map() {
+            let entry = field_descriptor
+                .containing_message()
+                .nested_messages()
+                .find(|m| m.full_name() == field.type_name.as_deref().unwrap_or_default())
+                .unwrap();
+            let key = entry.field_by_number(1).unwrap();
+            let value = entry.field_by_number(2).unwrap();
+            field.field_type = FieldKind::Map;
+            field.type_name = None;
+            field.map = Some(MapEntry {
+                key_type: FieldKind::from_type(key.proto().type_()),
+                value_type: FieldKind::from_type(value.proto().type_()),
+                value_type_name: Some(value.proto().type_name().trim_start_matches('.').to_owned()).filter(|n| !n.is_empty()),
+            });
+        }
+        field.oneof = field_descriptor.containing_oneof_including_synthetic().map(|o| o.name().to_owned());
+        let message_descriptor = field_descriptor.containing_message();
+        let file_descriptor_proto = message_descriptor.file_descriptor_proto();
+        let index = message_descriptor.proto().field.iter().position(|f| f.name() == field.name).unwrap();
+        field.location = source_info::message_path(file_descriptor_proto, message_descriptor.full_name())
+            .and_then(|p| SourceLocation::find(file_descriptor_proto, &[&p[..], &[2, index as i32]].concat()));
 
         field
     }
 }
 
-#[derive(Debug, Clone)]
+/// Key and value types of a `map<K, V>` field.
+#[derive(Debug, Clone, Default)]
+pub struct MapEntry {
+    pub key_type: FieldKind,
+    pub value_type: FieldKind,
+    /// Fully-qualified name of `V` when it is a message or an enum.
+    pub value_type_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Oneof {
+    pub name: String,
+    /// Generated for a proto3 `optional` field rather than declared in the source.
+    pub synthetic: bool,
+    /// Names of the member fields.
+    pub fields: Vec<String>,
+}
+
+impl Oneof {
+    fn from_descriptor(oneof_descriptor: OneofDescriptor) -> Self {
+        let mut oneof = Oneof::default();
+        oneof.name = oneof_descriptor.name().to_owned();
+        oneof.synthetic = oneof_descriptor.is_synthetic();
+        oneof.fields = oneof_descriptor.fields().map(|f| f.name().to_owned()).collect();
+
+        oneof
+    }
+}
+
+/// Cardinality of a field as declared in the `.proto` source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FieldLabel {
+    /// proto3 singular field without `optional`, no presence tracking.
+    #[default]
+    Implicit,
+    /// proto2 `optional`.
+    Optional,
+    /// proto2 `required`.
+    Required,
+    /// proto3 `optional`, tracked through a synthetic oneof.
+    Proto3Optional,
+    Repeated,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum FieldKind {
+    #[default]
     Unknown = 0,
     Double,
     Float,
@@ -93,25 +345,61 @@ pub enum FieldKind {
     Sfixed64,
     Sint32,
     Sint64,
+    /// `map<K, V>`, see [`Field::map`].
+    Map,
 }
 
-impl Default for FieldKind {
-    fn default() -> Self {
-        FieldKind::Unknown
+impl FieldKind {
+    fn from_type(t: Type) -> Self {
+        match t {
+            Type::TYPE_DOUBLE => FieldKind::Double,
+            Type::TYPE_FLOAT => FieldKind::Float,
+            Type::TYPE_INT64 => FieldKind::Int64,
+            Type::TYPE_UINT64 => FieldKind::Uint64,
+            Type::TYPE_INT32 => FieldKind::Int32,
+            Type::TYPE_FIXED64 => FieldKind::Fixed64,
+            Type::TYPE_FIXED32 => FieldKind::Fixed32,
+            Type::TYPE_BOOL => FieldKind::Bool,
+            Type::TYPE_STRING => FieldKind::String,
+            Type::TYPE_GROUP => FieldKind::Group,
+            Type::TYPE_MESSAGE => FieldKind::Message,
+            Type::TYPE_BYTES => FieldKind::Bytes,
+            Type::TYPE_UINT32 => FieldKind::Uint32,
+            Type::TYPE_ENUM => FieldKind::Enum,
+            Type::TYPE_SFIXED32 => FieldKind::Sfixed32,
+            Type::TYPE_SFIXED64 => FieldKind::Sfixed64,
+            Type::TYPE_SINT32 => FieldKind::Sint32,
+            Type::TYPE_SINT64 => FieldKind::Sint64,
+        }
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Service {
-    name: String,
-    methods: Vec<Method>,
+    pub name: String,
+    pub full_name: String,
+    pub methods: Vec<Method>,
+    pub location: Option<SourceLocation>,
 }
 
 impl Service {
-    fn from_descriptor_proto(descriptor_proto: ServiceDescriptorProto) -> Self {
+    /// `index` is the position of the service in `file_descriptor_proto`.
+    fn from_descriptor_proto(descriptor_proto: ServiceDescriptorProto, file_descriptor_proto: &FileDescriptorProto, index: usize) -> Self {
         let mut service = Service::default();
+        let path = [6, index as i32];
         service.name = descriptor_proto.name().to_owned();
-        service.methods = descriptor_proto.method.into_iter().map(|m| Method::from_descriptor_proto(m)).collect();
+        service.full_name = qualified_name(&service.name, file_descriptor_proto.package());
+        service.location = SourceLocation::find(file_descriptor_proto, &path);
+        service.methods = descriptor_proto
+            .method
+            .into_iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let mut method = Method::from_descriptor_proto(m, &service.full_name);
+                method.location = SourceLocation::find(file_descriptor_proto, &[path[0], path[1], 2, i as i32]);
+                method
+            })
+            .collect();
 
         service
     }
@@ -119,35 +407,90 @@ impl Service {
 
 #[derive(Debug, Clone, Default)]
 pub struct Method {
-    name: String,
-    kind: MethodKind,
-    input_type: String,
+    pub name: String,
+    /// HTTP/2 request path, `/package.Service/Method`.
+    pub path: String,
+    pub kind: MethodKind,
+    pub input_type: String,
+    pub output_type: String,
+    pub deprecated: bool,
+    pub idempotency_level: IdempotencyLevel,
+    pub location: Option<SourceLocation>,
 }
 
 impl Method {
-    fn from_descriptor_proto(method_descriptor_proto: MethodDescriptorProto) -> Self {
+    fn from_descriptor_proto(method_descriptor_proto: MethodDescriptorProto, service_full_name: &str) -> Self {
         let mut method = Method::default();
 
         method.name = method_descriptor_proto.name().to_owned();
+        method.path = format!("/{}/{}", service_full_name, method.name);
 
-        let client_streaming = method_descriptor_proto.has_client_streaming() as u8;
-        let server_streaming = (method_descriptor_proto.has_server_streaming() as u8) << 1;
-        match client_streaming & server_streaming {
-            0b00 => method.kind = MethodKind::Unary,
-            0b10 => method.kind = MethodKind::ClientStreaming,
-            0b01 => method.kind = MethodKind::ServerStreaming,
-            0b11 => method.kind = MethodKind::BidirectionalStreaming,
-            _ => unreachable!()
-        }
+        method.kind = match (method_descriptor_proto.client_streaming(), method_descriptor_proto.server_streaming()) {
+            (false, false) => MethodKind::Unary,
+            (true, false) => MethodKind::ClientStreaming,
+            (false, true) => MethodKind::ServerStreaming,
+            (true, true) => MethodKind::BidirectionalStreaming,
+        };
+
+        method.input_type = method_descriptor_proto.input_type().trim_start_matches('.').to_owned();
+        method.output_type = method_descriptor_proto.output_type().trim_start_matches('.').to_owned();
 
-        method.input_type = method_descriptor_proto.input_type().to_owned();
+        let options = method_descriptor_proto.options.get_or_default();
+        method.deprecated = options.deprecated();
+        method.idempotency_level = match options.idempotency_level() {
+            method_options::IdempotencyLevel::IDEMPOTENCY_UNKNOWN => IdempotencyLevel::Unknown,
+            method_options::IdempotencyLevel::NO_SIDE_EFFECTS => IdempotencyLevel::NoSideEffects,
+            method_options::IdempotencyLevel::IDEMPOTENT => IdempotencyLevel::Idempotent,
+        };
 
         method
     }
 }
 
-#[derive(Debug, Clone)]
+/// Where an element is declared, with the comments attached to it.
+#[derive(Debug, Clone, Default)]
+pub struct SourceLocation {
+    /// Proto name of the declaring file.
+    pub file: String,
+    /// 1-based start and end of the declaration, end exclusive.
+    pub line: u32,
+    pub column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+    /// Comment directly above the declaration, without comment markers.
+    pub leading_comments: String,
+    /// Comment after the declaration, on the same or the next line.
+    pub trailing_comments: String,
+    /// Comments before the leading comment, separated from it by blank lines.
+    pub detached_comments: Vec<String>,
+}
+
+impl SourceLocation {
+    fn find(file_descriptor_proto: &FileDescriptorProto, path: &[i32]) -> Option<Self> {
+        let location = source_info::find(file_descriptor_proto, path)?;
+        let span: Vec<u32> = location.span.iter().map(|&n| n as u32 + 1).collect();
+        let (line, column, end_line, end_column) = match span[..] {
+            [line, column, end_column] => (line, column, line, end_column),
+            [line, column, end_line, end_column] => (line, column, end_line, end_column),
+            _ => return None,
+        };
+
+        Some(SourceLocation {
+            file: file_descriptor_proto.name().to_owned(),
+            line,
+            column,
+            end_line,
+            end_column,
+            leading_comments: location.leading_comments().to_owned(),
+            trailing_comments: location.trailing_comments().to_owned(),
+            detached_comments: location.leading_detached_comments.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum MethodKind {
+    #[default]
     Unknown,
     Unary,
     ClientStreaming,
@@ -155,19 +498,1284 @@ pub enum MethodKind {
     BidirectionalStreaming,
 }
 
-impl Default for MethodKind {
+/// `option idempotency_level` of a method.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IdempotencyLevel {
+    #[default]
+    Unknown,
+    NoSideEffects,
+    Idempotent,
+}
+
+/// Load `paths` and every file they import.
+///
+/// There is one result per path, in order, followed by one for every imported file. A file
+/// failing to load doesn't prevent the others from loading.
+pub fn load_proto_from_files(paths: Vec<String>, workspace: Workspace) -> Result<Vec<ProtoFileResult>> {
+    let inputs: Vec<&str> = paths.iter().map(String::as_str).collect();
+    let (pool, results) = load_files(&inputs, &workspace)?;
+    let pool = schema::register(pool);
+
+    let mut loaded = HashSet::new();
+    let mut files: Vec<ProtoFileResult> = paths
+        .into_iter()
+        .zip(results)
+        .map(|(path, result)| {
+            if let Ok(name) = &result {
+                loaded.insert(name.clone());
+            }
+            ProtoFileResult::new(path, result, &pool)
+        })
+        .collect();
+    files.extend(pool.files().iter().filter(|f| !loaded.contains(f.name())).map(ProtoFileResult::imported));
+
+    Ok(files)
+}
+
+/// Load the schema from a serialized `FileDescriptorSet`, e.g. the output of
+/// `protoc -o descriptor.pb --include_imports`.
+///
+/// There is one result per file in the set: first the files nothing else in the set
+/// imports, then the imported ones. The set must contain every imported file.
+pub fn load_proto_from_descriptor_set(bytes: Vec<u8>) -> Result<Vec<ProtoFileResult>> {
+    Ok(self_contained_results(&schema::register(DescriptorPool::from_descriptor_set(&bytes)?)))
+}
+
+/// Results for every file in a pool that wasn't loaded from user-provided paths, listing
+/// files no other file imports first.
+fn self_contained_results(pool: &DescriptorPool) -> Vec<ProtoFileResult> {
+    let imported: HashSet<&str> = pool.files().iter().flat_map(|f| f.proto().dependency.iter().map(String::as_str)).collect();
+
+    let (imports, inputs): (Vec<_>, Vec<_>) = pool.files().iter().partition(|f| imported.contains(f.name()));
+    let mut files: Vec<ProtoFileResult> = inputs.into_iter().map(|f| ProtoFileResult::new(f.name().to_owned(), Ok(f.name().to_owned()), pool)).collect();
+    files.extend(imports.into_iter().map(ProtoFileResult::imported));
+
+    files
+}
+
+/// Like [`load_proto_from_descriptor_set`], for descriptor set files on disk.
+///
+/// The sets are merged, so a file may rely on imports shipped in another one.
+pub fn load_proto_from_descriptor_set_files(paths: Vec<String>) -> Result<Vec<ProtoFileResult>> {
+    let mut bytes = Vec::new();
+    for path in &paths {
+        // Serialized messages concatenate into their merge, i.e. one set with every file.
+        bytes.extend(std::fs::read(path).with_context(|| format!("failed to read `{}`", path))?);
+    }
+
+    load_proto_from_descriptor_set(bytes)
+}
+
+/// Fully-qualified names of the services the server at `target` (`host:port`) lists
+/// through its reflection service, connecting with `options` like [`call_unary`] does and
+/// sending `metadata` as request headers.
+pub fn list_services_from_reflection(target: String, options: CallOptions, metadata: Vec<MetadataEntry>) -> Result<Vec<String>> {
+    let metadata = metadata::to_header_map(&metadata)?;
+    runtime().block_on(async {
+        let mut client = ReflectionClient::connect(&target, &options, metadata).await?;
+        client.list_services().await
+    })
+}
+
+/// Fetch the schema of the server at `target` through its reflection service, like
+/// [`load_proto_from_descriptor_set`] does for a descriptor set, connecting and sending
+/// `metadata` like [`list_services_from_reflection`] does.
+///
+/// Only the files declaring `symbols` (fully-qualified service, message or enum names) and
+/// their imports are fetched, or those of every listed service when `symbols` is empty.
+pub fn load_proto_from_reflection(target: String, options: CallOptions, metadata: Vec<MetadataEntry>, symbols: Vec<String>) -> Result<Vec<ProtoFileResult>> {
+    let metadata = metadata::to_header_map(&metadata)?;
+    let files = runtime().block_on(reflection::fetch_files(&target, &options, metadata, &symbols))?;
+
+    Ok(self_contained_results(&schema::register(DescriptorPool::new(files)?)))
+}
+
+/// Progress of [`load_proto_stream`].
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum ProtoLoadEvent {
+    /// The `index`-th of `total` input files is about to be loaded.
+    Progress { path: String, index: u32, total: u32 },
+    /// An input file finished loading, successfully or not, or an imported file was loaded
+    /// along with it.
+    File(ProtoFileResult),
+    /// The load was cancelled before every file was processed.
+    Cancelled,
+    /// Every file was processed.
+    Done,
+}
+
+impl ProtoFileResult {
+    fn new(path: String, result: Result<String, ProtoError>, pool: &DescriptorPool) -> Self {
+        match result {
+            Ok(name) => ProtoFileResult {
+                path,
+                proto: Some(Proto::from_descriptor(pool.file_by_name(&name).unwrap())),
+                ..Default::default()
+            },
+            Err(error) => ProtoFileResult { path, error: Some(error), ..Default::default() },
+        }
+    }
+
+    fn imported(file_descriptor: &FileDescriptor) -> Self {
+        ProtoFileResult {
+            path: file_descriptor.name().to_owned(),
+            imported: true,
+            proto: Some(Proto::from_descriptor(file_descriptor)),
+            error: None,
+        }
+    }
+}
+
+pub fn create_cancel_token() -> RustOpaque<CancelToken> {
+    RustOpaque::new(CancelToken::default())
+}
+
+pub fn cancel(token: RustOpaque<CancelToken>) {
+    token.cancel();
+}
+
+/// Like [`load_proto_from_files`], but report each file as soon as it is loaded.
+///
+/// Cancelling `cancel` stops the load before the next file.
+pub fn load_proto_stream(
+    paths: Vec<String>,
+    workspace: Workspace,
+    cancel: RustOpaque<CancelToken>,
+    sink: StreamSink<ProtoLoadEvent>,
+) -> Result<()> {
+    let result = stream_proto_files(paths, &workspace, &cancel, &|event| sink.add(event));
+    sink.close();
+
+    result
+}
+
+/// `emit` returns `false` once nobody is listening anymore, which stops the load like
+/// cancelling does.
+fn stream_proto_files(
+    paths: Vec<String>,
+    workspace: &Workspace,
+    cancel: &CancelToken,
+    emit: &dyn Fn(ProtoLoadEvent) -> bool,
+) -> Result<()> {
+    let inputs: Vec<&str> = paths.iter().map(String::as_str).collect();
+    let mut loader = Loader::new(&inputs, workspace)?;
+
+    for (index, path) in paths.iter().enumerate() {
+        let progress = ProtoLoadEvent::Progress { path: path.clone(), index: index as u32, total: paths.len() as u32 };
+        if cancel.is_cancelled() || !emit(progress) {
+            schema::register(loader.into_pool());
+            emit(ProtoLoadEvent::Cancelled);
+            return Ok(());
+        }
+
+        let before = loader.pool().files().len();
+        let result = loader.load(index);
+        let name = result.as_ref().ok().cloned();
+        emit(ProtoLoadEvent::File(ProtoFileResult::new(path.clone(), result, loader.pool())));
+        for file in &loader.pool().files()[before..] {
+            if Some(file.name()) != name.as_deref() {
+                emit(ProtoLoadEvent::File(ProtoFileResult::imported(file)));
+            }
+        }
+    }
+    schema::register(loader.into_pool());
+    emit(ProtoLoadEvent::Done);
+
+    Ok(())
+}
+
+/// How to secure the connection to a server with TLS.
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    /// PEM file of the certificate authorities to trust instead of the system's.
+    pub ca_certificate_path: Option<String>,
+    /// Certificate to present to servers that require one.
+    pub client_certificate: Option<ClientCertificate>,
+    /// `host` or `host:port` to send as `:authority` instead of the target's, its host being
+    /// used for SNI and to verify the server certificate.
+    pub authority: Option<String>,
+    /// Accept any server certificate. Traffic is still encrypted but can be intercepted, so
+    /// this is only for servers with self-signed certificates the user chose to trust.
+    pub insecure_skip_verify: bool,
+}
+
+/// Settings of a single call.
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+    /// How to secure the connection to the server, see [`call_unary`].
+    pub tls: Option<TlsSettings>,
+    /// How long the call may take, in milliseconds, from connecting to the server until it
+    /// ends. The server is told through `grpc-timeout`, and the client resets the call if it
+    /// hasn't ended by then. No deadline when absent.
+    pub timeout_ms: Option<u32>,
+    /// How requests are compressed. Responses are decompressed whatever encoding the server
+    /// picks among those supported.
+    pub compression: Compression,
+}
+
+/// Encoding of compressed messages, sent as `grpc-encoding`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Uncompressed.
+    #[default]
+    Identity,
+    Gzip,
+    /// zlib, as `deflate` is in HTTP.
+    Deflate,
+    Zstd,
+}
+
+/// Size of a message in bytes, without the 5 bytes gRPC frames it with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageSize {
+    /// As sent, the same as `uncompressed` for a message that wasn't compressed.
+    pub compressed: u32,
+    pub uncompressed: u32,
+}
+
+/// A client certificate and its private key.
+#[derive(Debug, Clone)]
+pub enum ClientCertificate {
+    /// PEM files of the certificate chain, leaf first, and of the key.
+    Pem { certificate_path: String, private_key_path: String },
+    /// A PKCS#12 (`.p12` or `.pfx`) archive holding both.
+    Pkcs12 { path: String, password: String },
+}
+
+/// A request or response header, or a response trailer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataEntry {
+    /// Lowercase ASCII letters, digits, `-`, `_` and `.`; uppercase letters are lowercased
+    /// when sent. Keys starting with `grpc-` are reserved.
+    pub key: String,
+    pub value: MetadataValue,
+}
+
+/// Value of a [`MetadataEntry`]: binary for keys ending in `-bin`, text otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataValue {
+    /// Printable ASCII.
+    Text(String),
+    /// Any bytes, base64-encoded on the wire.
+    Binary(Vec<u8>),
+}
+
+impl Default for MetadataValue {
+    fn default() -> Self {
+        MetadataValue::Text(String::new())
+    }
+}
+
+/// Why a [`MetadataEntry`] can't be sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataProblem {
+    /// Position of the entry in the list checked.
+    pub index: u32,
+    pub message: String,
+}
+
+/// Check `metadata` as the editor changes it, before it is sent with a call.
+pub fn validate_metadata(metadata: Vec<MetadataEntry>) -> Vec<MetadataProblem> {
+    metadata
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entry)| metadata::header(entry).err().map(|message| MetadataProblem { index: index as u32, message }))
+        .collect()
+}
+
+/// Status a call ended with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallStatus {
+    pub code: i32,
+    /// Name of the code, e.g. `NOT_FOUND`.
+    pub name: String,
+    pub message: String,
+    /// Details the server sent in `grpc-status-details-bin`, e.g. `google.rpc.BadRequest`.
+    pub details: Vec<StatusDetail>,
+    pub origin: StatusOrigin,
+}
+
+impl From<grpc::Status> for CallStatus {
+    fn from(status: grpc::Status) -> Self {
+        CallStatus { code: status.code, name: grpc::code_name(status.code).to_owned(), message: status.message, details: call::status_details(&status.details), origin: status.origin }
+    }
+}
+
+/// Which side ended a call with a [`CallStatus`].
+///
+/// A server giving up on its own deadline returns `DEADLINE_EXCEEDED` with the `Server`
+/// origin, while `Deadline` means the client stopped waiting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatusOrigin {
+    /// The server ended the call.
+    #[default]
+    Server,
+    /// The deadline set in [`CallOptions`] passed, `DEADLINE_EXCEEDED`.
+    Deadline,
+    /// The user cancelled the call, `CANCELLED`.
+    Cancelled,
+    /// The server sent a response larger than the client accepts, `RESOURCE_EXHAUSTED`.
+    Rejected,
+}
+
+/// An entry of the `details` of a `google.rpc.Status`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusDetail {
+    /// Names the type of the detail, e.g. `type.googleapis.com/google.rpc.ErrorInfo`.
+    pub type_url: String,
+    /// The detail as proto3 JSON, absent when its type is neither in `google/rpc` nor in a
+    /// loaded proto.
+    pub json: Option<String>,
+    /// The serialized detail.
+    pub value: Vec<u8>,
+}
+
+/// Outcome of a unary call.
+#[derive(Debug, Clone, Default)]
+pub struct CallResponse {
+    /// The response message as JSON, absent when the call failed.
+    pub response: Option<String>,
+    pub status: CallStatus,
+    pub headers: Vec<MetadataEntry>,
+    pub trailers: Vec<MetadataEntry>,
+    /// Size of the request, absent when it couldn't be sent.
+    pub request_size: Option<MessageSize>,
+    /// Size of the response, absent when none was received.
+    pub response_size: Option<MessageSize>,
+}
+
+/// Call the unary method `method` of the server at `target` (`host:port`) with the request
+/// `request_json`, sending `metadata` as request headers.
+///
+/// The connection is secured with the TLS settings of `options` when given, or with default
+/// settings when `target` starts with `https://`.
+///
+/// `method` is `package.Service/Method` or `package.Service.Method` and must be declared in
+/// a loaded proto. An error status returned by the server is reported in the response, as
+/// are the deadline of `options` passing and `cancel` being cancelled, which end the call
+/// with a client-side [`StatusOrigin`]; errors are for invalid requests and transport
+/// failures.
+pub fn call_unary(target: String, options: CallOptions, method: String, request_json: String, metadata: Vec<MetadataEntry>, cancel: RustOpaque<CancelToken>) -> Result<CallResponse> {
+    let metadata = metadata::to_header_map(&metadata)?;
+    let response = runtime().block_on(call::unary(&target, &options, &method, &request_json, &metadata, &cancel))?;
+
+    Ok(CallResponse {
+        response: response.message,
+        status: response.status.into(),
+        headers: metadata::from_header_map(&response.headers),
+        trailers: metadata::from_header_map(&response.trailers),
+        request_size: response.request_size,
+        response_size: response.response_size,
+    })
+}
+
+/// Progress of a streaming call.
+#[derive(Debug, Clone)]
+pub enum CallEvent {
+    /// The server accepted the call and sent its response headers.
+    Headers(Vec<MetadataEntry>),
+    /// A response message, received `received_at` microseconds after the Unix epoch.
+    Message { json: String, received_at: i64, size: MessageSize },
+    /// The call ended, on the server or when its deadline passed, as told by the origin of
+    /// `status`.
+    Finished { status: CallStatus, trailers: Vec<MetadataEntry> },
+    /// The call was cancelled before the server ended it.
+    Cancelled,
+}
+
+impl From<call::Event> for CallEvent {
+    fn from(event: call::Event) -> Self {
+        match event {
+            call::Event::Headers(headers) => CallEvent::Headers(metadata::from_header_map(&headers)),
+            call::Event::Message { json, received_at, size } => {
+                let received_at = received_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as i64;
+                CallEvent::Message { json, received_at, size }
+            }
+            call::Event::Finished { status, trailers } => CallEvent::Finished { status: status.into(), trailers: metadata::from_header_map(&trailers) },
+            call::Event::Cancelled => CallEvent::Cancelled,
+        }
+    }
+}
+
+/// Call the server-streaming method `method` like [`call_unary`] does, reporting each
+/// response as soon as it is received.
+///
+/// Cancelling `cancel` ends the call on both sides, reported as [`CallEvent::Cancelled`].
+pub fn call_server_streaming(
+    target: String,
+    options: CallOptions,
+    method: String,
+    request_json: String,
+    metadata: Vec<MetadataEntry>,
+    cancel: RustOpaque<CancelToken>,
+    sink: StreamSink<CallEvent>,
+) -> Result<()> {
+    let result = metadata::to_header_map(&metadata).and_then(|metadata| {
+        runtime().block_on(call::server_streaming(&target, &options, &method, &request_json, &metadata, &cancel, &|event| sink.add(event.into())))
+    });
+    sink.close();
+
+    result
+}
+
+/// Start a call of the client-streaming or bidirectional streaming method `method` of the
+/// server at `target`, connecting with `options` like [`call_unary`] does and sending
+/// `metadata` as request headers.
+///
+/// Requests are then sent with [`session_send`] and [`session_half_close`], and responses
+/// received through [`session_receive`]. The deadline of `options` covers the whole
+/// session, from now until the last response.
+pub fn open_session(target: String, options: CallOptions, method: String, metadata: Vec<MetadataEntry>) -> Result<RustOpaque<CallSession>> {
+    let metadata = metadata::to_header_map(&metadata)?;
+    let session = runtime().block_on(CallSession::open(&target, &options, &method, &metadata))?;
+
+    Ok(RustOpaque::new(session))
+}
+
+/// Send a request, given as JSON, on the call of `session`, returning its size.
+pub fn session_send(session: RustOpaque<CallSession>, request_json: String) -> Result<MessageSize> {
+    session.send(&request_json)
+}
+
+/// Tell the server no more requests will be sent on the call of `session`.
+pub fn session_half_close(session: RustOpaque<CallSession>) -> Result<()> {
+    session.half_close()
+}
+
+/// Report the responses of the call of `session` as they arrive, until the call ends.
+pub fn session_receive(session: RustOpaque<CallSession>, sink: StreamSink<CallEvent>) -> Result<()> {
+    let result = runtime().block_on(session.receive(&|event| sink.add(event.into())));
+    sink.close();
+
+    result
+}
+
+/// Reset the call of `session`, ending it on both sides.
+pub fn session_cancel(session: RustOpaque<CallSession>) {
+    session.cancel();
+}
+
+/// A JSON request body for the message `message_name`, a fully-qualified name from a loaded
+/// proto, with every field set to a sample value.
+///
+/// Only the first member of each oneof is set and repeated and map fields get one element.
+/// A message type is expanded at most `recursion_limit` times along any path, deeper
+/// occurrences being left as `{}`.
+pub fn request_template(message_name: String, recursion_limit: u32) -> Result<String> {
+    let (descriptor, _) = schema::message_by_name(&message_name)?;
+
+    Ok(serde_json::to_string_pretty(&template::template(&descriptor, recursion_limit))?)
+}
+
+/// How a payload given as text is encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PayloadEncoding {
+    /// Hex digits, optionally prefixed with `0x` and split by whitespace.
+    #[default]
+    Hex,
+    /// Standard or URL-safe base64.
+    Base64,
+}
+
+/// How a protobuf field is encoded on the wire.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireType {
+    #[default]
+    Varint,
+    Fixed64,
+    LengthDelimited,
+    StartGroup,
+    EndGroup,
+    Fixed32,
+}
+
+/// A field present in a payload but not declared by the message it was decoded as.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnknownField {
+    /// JSON pointer to the message holding the field, empty for the decoded message itself.
+    pub path: String,
+    pub number: u32,
+    pub wire_type: WireType,
+    /// Decimal for numbers, base64 for length-delimited values.
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodedMessage {
+    /// The message as canonical proto3 JSON, without its unknown fields.
+    pub json: String,
+    pub unknown_fields: Vec<UnknownField>,
+}
+
+/// Decode `payload`, a serialized message encoded as `encoding`, as the message
+/// `message_name`, a fully-qualified name from a loaded proto.
+pub fn decode_message(message_name: String, payload: String, encoding: PayloadEncoding) -> Result<DecodedMessage> {
+    let bytes = match encoding {
+        PayloadEncoding::Hex => decode::parse_hex(&payload)?,
+        PayloadEncoding::Base64 => decode::parse_base64(&payload)?,
+    };
+
+    decode_message_bytes(&message_name, &bytes)
+}
+
+/// Like [`decode_message`], for a serialized message stored in a file.
+pub fn decode_message_file(message_name: String, path: String) -> Result<DecodedMessage> {
+    let bytes = std::fs::read(&path).with_context(|| format!("failed to read `{}`", path))?;
+
+    decode_message_bytes(&message_name, &bytes)
+}
+
+fn decode_message_bytes(message_name: &str, bytes: &[u8]) -> Result<DecodedMessage> {
+    let (descriptor, pool) = schema::message_by_name(message_name)?;
+    let message = descriptor.parse_from_bytes(bytes).with_context(|| format!("payload is not a valid {}", descriptor.full_name()))?;
+    let json = json::print(&*message, &pool, true).map_err(|e| anyhow!("failed to print {}: {}", descriptor.full_name(), e))?;
+    let unknown_fields = decode::unknown_fields(&*message);
+
+    Ok(DecodedMessage { json, unknown_fields })
+}
+
+/// A field of a message decoded without knowing its type.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawField {
+    pub number: u32,
+    pub wire_type: WireType,
+    /// Position of the field, tag included, in the payload.
+    pub offset: u32,
+    pub length: u32,
+    pub value: RawValue,
+}
+
+/// Value of a [`RawField`]. What a length-delimited value holds is guessed: text, then a
+/// nested message, then packed varints, and bytes when nothing else fits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RawValue {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    String(String),
+    Message(Vec<RawField>),
+    PackedVarints(Vec<u64>),
+    Bytes(Vec<u8>),
+    Group(Vec<RawField>),
+}
+
+impl Default for RawValue {
     fn default() -> Self {
-        MethodKind::Unknown
+        RawValue::Varint(0)
+    }
+}
+
+/// A loaded message a raw payload could be an instance of.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeGuess {
+    pub message_name: String,
+    /// `matched_fields / total_fields`.
+    pub score: f64,
+    /// Fields, nested ones included, declared by the message with a matching wire type.
+    pub matched_fields: u32,
+    pub total_fields: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RawMessage {
+    pub fields: Vec<RawField>,
+    /// Best candidates first, empty unless asked for.
+    pub type_guesses: Vec<TypeGuess>,
+}
+
+/// Decode `payload`, a serialized message of unknown type encoded as `encoding`, into its
+/// fields like `protoc --decode_raw` does.
+///
+/// With `guess_type`, every message of the loaded protos is ranked by how well the fields
+/// fit it.
+pub fn decode_raw(payload: String, encoding: PayloadEncoding, guess_type: bool) -> Result<RawMessage> {
+    let bytes = match encoding {
+        PayloadEncoding::Hex => decode::parse_hex(&payload)?,
+        PayloadEncoding::Base64 => decode::parse_base64(&payload)?,
+    };
+
+    decode_raw_bytes(&bytes, guess_type)
+}
+
+/// Like [`decode_raw`], for a serialized message stored in a file.
+pub fn decode_raw_file(path: String, guess_type: bool) -> Result<RawMessage> {
+    let bytes = std::fs::read(&path).with_context(|| format!("failed to read `{}`", path))?;
+
+    decode_raw_bytes(&bytes, guess_type)
+}
+
+/// At most this many type guesses are returned.
+const MAX_TYPE_GUESSES: usize = 10;
+
+fn decode_raw_bytes(bytes: &[u8], guess_type: bool) -> Result<RawMessage> {
+    let fields = decode::decode_raw(bytes)?;
+    let mut type_guesses = Vec::new();
+    if guess_type {
+        type_guesses = decode::guess_types(&fields, &schema::messages());
+        type_guesses.truncate(MAX_TYPE_GUESSES);
     }
+
+    Ok(RawMessage { fields, type_guesses })
 }
 
-// TODO: use a stream instead
-pub fn load_proto_from_files(paths: Vec<String>) -> Result<ZeroCopyBuffer<Vec<Proto>>> {
-    Ok(ZeroCopyBuffer(paths.into_iter().map(|p| Proto::from_file(&p).unwrap()).collect()))
+/// A problem in a JSON message, located for the editor to underline.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonDiagnostic {
+    /// JSON pointer to the offending value, empty for the document itself.
+    pub path: String,
+    pub message: String,
+    /// Range of the offending text in UTF-16 code units, as Dart indexes strings. It covers
+    /// the key of unknown fields and extra oneof members, and the value otherwise.
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Check `json` against the message `message_name`, a fully-qualified name from a loaded
+/// proto, before it is sent.
+///
+/// Every problem is returned rather than the first one, so an empty list means the request
+/// can be encoded. A JSON syntax error is reported alone, as an empty range where it is.
+pub fn validate_json(message_name: String, json: String) -> Result<Vec<JsonDiagnostic>> {
+    let (descriptor, pool) = schema::message_by_name(&message_name)?;
+
+    Ok(validate::validate(&descriptor, &json, &pool))
+}
+
+/// A way of writing a message down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Proto3 JSON.
+    #[default]
+    Json,
+    /// Protobuf text format, as used by `protoc --encode`.
+    Text,
+    /// The binary encoding as hex digits.
+    Hex,
+    /// The binary encoding as base64.
+    Base64,
+}
+
+/// What is wrong with the input of a conversion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputError {
+    /// 1-based position of the error in the input, when it has one.
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConvertedMessage {
+    /// The message written as asked, unless the input is invalid.
+    pub output: Option<String>,
+    pub error: Option<InputError>,
+}
+
+/// Convert `input`, a message `message_name` written as `from`, to `to`.
+///
+/// An invalid input is reported in the result, with its line and column for text format
+/// and JSON syntax errors; an unknown message is an error.
+pub fn convert_message(message_name: String, input: String, from: MessageFormat, to: MessageFormat) -> Result<ConvertedMessage> {
+    let (descriptor, pool) = schema::message_by_name(&message_name)?;
+    let error = |line, column, message| ConvertedMessage { output: None, error: Some(InputError { line, column, message }) };
+
+    let message = match from {
+        MessageFormat::Json => match serde_json::from_str(&input) {
+            Ok(value) => match json::from_value(&descriptor, &value, &pool) {
+                Ok(message) => message,
+                Err(e) => return Ok(error(None, None, e.to_string())),
+            },
+            Err(e) => return Ok(error(Some(e.line() as u32), Some(e.column() as u32), format!("invalid JSON: {}", e))),
+        },
+        MessageFormat::Text => match text_format::parse(&descriptor, &input, &pool) {
+            Ok(message) => message,
+            Err(e) => return Ok(error(Some(e.line), Some(e.column), e.message)),
+        },
+        MessageFormat::Hex | MessageFormat::Base64 => {
+            let bytes = match from {
+                MessageFormat::Hex => decode::parse_hex(&input),
+                _ => decode::parse_base64(&input),
+            };
+            match bytes.and_then(|b| descriptor.parse_from_bytes(&b).with_context(|| format!("payload is not a valid {}", descriptor.full_name()))) {
+                Ok(message) => message,
+                Err(e) => return Ok(error(None, None, format!("{:#}", e))),
+            }
+        }
+    };
+
+    let output = match to {
+        MessageFormat::Json => json::print(&*message, &pool, true).map_err(|e| anyhow!("failed to print {}: {}", descriptor.full_name(), e))?,
+        MessageFormat::Text => text_format::print(&*message, &pool),
+        MessageFormat::Hex => codec::encode(&*message)?.iter().map(|b| format!("{:02x}", b)).collect(),
+        MessageFormat::Base64 => json::encode_base64(&codec::encode(&*message)?),
+    };
+
+    Ok(ConvertedMessage { output: Some(output), error: None })
 }
 
 #[test]
 fn load_proto() {
-    let proto = Proto::from_file(r"D:\Developer\Projects\GraduationProject\backend\service\proto\auth.proto").unwrap();
-    println!("{:#?}", proto);
+    let proto = Proto::from_file("testdata/imports/user.proto", &Workspace::default()).unwrap();
+    assert_eq!((proto.name.as_str(), proto.package.as_str()), ("user.proto", "user"));
+    assert_eq!(proto.dependencies, ["common/types.proto"]);
+    let messages: Vec<&str> = proto.messages.iter().map(|m| m.full_name.as_str()).collect();
+    assert_eq!(messages, ["user.User", "user.GetUserRequest"]);
+    assert_eq!(proto.messages[0].fields[1].type_name.as_deref(), Some("common.Audit"));
+    assert_eq!(proto.services[0].methods[0].path, "/user.UserService/GetUser");
+}
+
+#[test]
+fn field_types_and_labels() {
+    let proto = Proto::from_file("testdata/fields/legacy.proto", &Workspace::default()).unwrap();
+    let fields = &proto.messages[0].fields;
+
+    let id = &fields[0];
+    assert_eq!((id.field_type, id.label, id.number), (FieldKind::Sint64, FieldLabel::Required, 1));
+    assert_eq!(id.json_name, "userId");
+
+    let state = &fields[1];
+    assert_eq!(state.field_type, FieldKind::Enum);
+    assert_eq!(state.type_name.as_deref(), Some("legacy.State"));
+    assert_eq!(state.default_value.as_deref(), Some("ACTIVE"));
+    assert_eq!(state.label, FieldLabel::Optional);
+
+    let tags = &fields[2];
+    assert_eq!((tags.field_type, tags.label), (FieldKind::String, FieldLabel::Repeated));
+
+    let proto = Proto::from_file("testdata/imports/user.proto", &Workspace::default()).unwrap();
+    let created = &proto.messages[0].fields[1];
+    assert_eq!(created.field_type, FieldKind::Message);
+    assert_eq!(created.type_name.as_deref(), Some("common.Audit"));
+    assert_eq!(created.label, FieldLabel::Implicit);
+}
+
+#[test]
+fn method_kinds_and_paths() {
+    let proto = Proto::from_file("testdata/methods/chat.proto", &Workspace::default()).unwrap();
+    let service = &proto.services[0];
+    assert_eq!(service.full_name, "chat.v1.Chat");
+
+    let kinds: Vec<_> = service.methods.iter().map(|m| m.kind).collect();
+    assert_eq!(
+        kinds,
+        [MethodKind::Unary, MethodKind::ClientStreaming, MethodKind::ServerStreaming, MethodKind::BidirectionalStreaming]
+    );
+
+    let get = &service.methods[0];
+    assert_eq!(get.path, "/chat.v1.Chat/GetRoom");
+    assert_eq!((get.input_type.as_str(), get.output_type.as_str()), ("chat.v1.RoomRequest", "chat.v1.Room"));
+    assert_eq!(get.idempotency_level, IdempotencyLevel::NoSideEffects);
+    assert!(!get.deprecated);
+    assert!(service.methods[1].deprecated);
+}
+
+#[test]
+fn stream_reports_files_and_stops_when_cancelled() {
+    use std::cell::RefCell;
+
+    let paths = vec!["testdata/imports/user.proto".to_owned(), "testdata/errors/syntax.proto".to_owned()];
+    let events = RefCell::new(Vec::new());
+    stream_proto_files(paths.clone(), &Workspace::default(), &CancelToken::default(), &|e| {
+        events.borrow_mut().push(e);
+        true
+    })
+    .unwrap();
+
+    let events = events.into_inner();
+    assert!(matches!(&events[0], ProtoLoadEvent::Progress { index: 0, total: 2, .. }));
+    match &events[1] {
+        ProtoLoadEvent::File(f) => assert!(f.proto.is_some() && !f.imported),
+        e => panic!("unexpected event {:?}", e),
+    }
+    let imported = events.iter().filter(|e| matches!(e, ProtoLoadEvent::File(f) if f.imported)).count();
+    assert_eq!(imported, 2);
+    match &events[events.len() - 2] {
+        ProtoLoadEvent::File(f) => assert!(matches!(f.error, Some(ProtoError::Syntax { .. }))),
+        e => panic!("unexpected event {:?}", e),
+    }
+    assert!(matches!(events.last(), Some(ProtoLoadEvent::Done)));
+
+    let cancel = CancelToken::default();
+    let events = RefCell::new(Vec::new());
+    stream_proto_files(paths, &Workspace::default(), &cancel, &|e| {
+        if let ProtoLoadEvent::File(_) = e {
+            cancel.cancel();
+        }
+        events.borrow_mut().push(e);
+        true
+    })
+    .unwrap();
+    assert!(matches!(events.borrow().last(), Some(ProtoLoadEvent::Cancelled)));
+    assert_eq!(events.borrow().iter().filter(|e| matches!(e, ProtoLoadEvent::Progress { .. })).count(), 1);
+}
+
+#[test]
+fn nested_messages_and_enums() {
+    let proto = Proto::from_file("testdata/nested/catalog.proto", &Workspace::default()).unwrap();
+    assert_eq!(proto.enums[0].full_name, "catalog.Visibility");
+
+    let product = &proto.messages[0];
+    assert_eq!(product.full_name, "catalog.Product");
+    assert_eq!(product.nested_messages.len(), 1);
+    let variant = &product.nested_messages[0];
+    assert_eq!(variant.full_name, "catalog.Product.Variant");
+    assert_eq!(variant.enums[0].full_name, "catalog.Product.Variant.Size");
+    assert_eq!(product.fields[1].type_name.as_deref(), Some("catalog.Product.Variant"));
+
+    let status = &product.enums[0];
+    assert_eq!(status.full_name, "catalog.Product.Status");
+    let values: Vec<_> = status.values.iter().map(|v| (v.name.as_str(), v.number)).collect();
+    assert_eq!(values, [("DRAFT", 0), ("LIVE", 1), ("PUBLISHED", 1), ("RETIRED", 2)]);
+    assert_eq!(status.values[1].aliases, ["PUBLISHED"]);
+    assert!(status.values[3].deprecated);
+}
+
+#[test]
+fn oneofs_and_maps() {
+    let proto = Proto::from_file("testdata/nested/catalog.proto", &Workspace::default()).unwrap();
+    let product = &proto.messages[0];
+
+    let labels = product.fields.iter().find(|f| f.name == "labels").unwrap();
+    assert_eq!(labels.field_type, FieldKind::Map);
+    assert!(!labels.repeated);
+    let map = labels.map.as_ref().unwrap();
+    assert_eq!((map.key_type, map.value_type, map.value_type_name.as_deref()), (FieldKind::String, FieldKind::String, None));
+
+    let variants = product.fields.iter().find(|f| f.name == "by_sku").unwrap();
+    let map = variants.map.as_ref().unwrap();
+    assert_eq!((map.value_type, map.value_type_name.as_deref()), (FieldKind::Message, Some("catalog.Product.Variant")));
+
+    let oneofs: Vec<_> = product.oneofs.iter().map(|o| (o.name.as_str(), o.synthetic, o.fields.clone())).collect();
+    assert_eq!(
+        oneofs,
+        [
+            ("price", false, vec!["cents".to_owned(), "free".to_owned()]),
+            ("_discount", true, vec!["discount".to_owned()]),
+        ]
+    );
+    let cents = product.fields.iter().find(|f| f.name == "cents").unwrap();
+    assert_eq!(cents.oneof.as_deref(), Some("price"));
+}
+
+#[test]
+fn comments_and_spans() {
+    let proto = Proto::from_file("testdata/comments/greeter.proto", &Workspace::default()).unwrap();
+    assert_eq!(proto.location.as_ref().unwrap().detached_comments, [" File header.\n"]);
+
+    let service = proto.services[0].location.as_ref().unwrap();
+    assert_eq!(service.file, "greeter.proto");
+    assert_eq!((service.line, service.column, service.end_line, service.end_column), (10, 1, 13, 2));
+    assert_eq!(service.leading_comments, " Leading for Greeter.\n");
+
+    let method = proto.services[0].methods[0].location.as_ref().unwrap();
+    assert_eq!(method.trailing_comments, " Trailing for Hello.\n");
+
+    let message = &proto.messages[0];
+    assert_eq!(message.location.as_ref().unwrap().leading_comments, " Block\n comment. ");
+    let text = message.fields[0].location.as_ref().unwrap();
+    assert_eq!((text.line, text.column), (18, 3));
+    assert_eq!(text.trailing_comments, " Text to echo.\n");
+
+    let loud = proto.enums[0].values[1].location.as_ref().unwrap();
+    assert_eq!((loud.line, loud.leading_comments.as_str()), (27, " Shouted.\n"));
+}
+
+#[test]
+fn load_descriptor_set_files() {
+    use protobuf::Message as _;
+
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .include("testdata/imports")
+        .input("testdata/imports/user.proto")
+        .parse_and_typecheck()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let mut paths = Vec::new();
+    for file in parsed.file_descriptors {
+        let path = dir.path().join(file.name().replace('/', "_")).with_extension("pb");
+        let mut set = protobuf::descriptor::FileDescriptorSet::new();
+        set.file.push(file);
+        std::fs::write(&path, set.write_to_bytes().unwrap()).unwrap();
+        paths.push(path.display().to_string());
+    }
+
+    let files = load_proto_from_descriptor_set_files(paths.clone()).unwrap();
+    let names: Vec<_> = files.iter().map(|f| (f.path.as_str(), f.imported)).collect();
+    assert_eq!(names, [("user.proto", false), ("google/protobuf/timestamp.proto", true), ("common/types.proto", true)]);
+    let user = files[0].proto.as_ref().unwrap();
+    assert_eq!(user.services[0].methods[0].path, "/user.UserService/GetUser");
+
+    let user_only = paths.into_iter().filter(|p| p.ends_with("user.pb")).collect();
+    let err = load_proto_from_descriptor_set_files(user_only).unwrap_err();
+    assert!(err.to_string().contains("missing dependencies"));
+}
+
+#[test]
+fn load_schema_through_reflection() {
+    let descriptor_set = crate::test_server::descriptor_set("testdata/methods", "testdata/methods/chat.proto");
+    for v1alpha_only in [false, true] {
+        let target = crate::test_server::reflection_server(&descriptor_set, v1alpha_only);
+
+        let mut services = list_services_from_reflection(target.clone(), CallOptions::default(), Vec::new()).unwrap();
+        services.sort();
+        let reflection = if v1alpha_only { "grpc.reflection.v1alpha.ServerReflection" } else { "grpc.reflection.v1.ServerReflection" };
+        assert_eq!(services, ["chat.v1.Chat", reflection]);
+
+        let options = CallOptions { timeout_ms: Some(5000), ..CallOptions::default() };
+        let metadata = vec![MetadataEntry { key: "x-client".to_owned(), value: MetadataValue::Text("grpc_debug".to_owned()) }];
+        let files = load_proto_from_reflection(target.clone(), options, metadata, vec!["chat.v1.Chat".to_owned()]).unwrap();
+        let chat = files[0].proto.as_ref().unwrap();
+        assert_eq!(chat.name, "chat.proto");
+        assert_eq!(chat.services[0].methods[3].kind, MethodKind::BidirectionalStreaming);
+
+        let err = load_proto_from_reflection(target.clone(), CallOptions::default(), Vec::new(), vec!["chat.v1.Missing".to_owned()]).unwrap_err();
+        assert!(err.to_string().contains("NOT_FOUND"), "{}", err);
+
+        let reserved = vec![MetadataEntry { key: "grpc-timeout".to_owned(), value: MetadataValue::Text("1S".to_owned()) }];
+        assert!(list_services_from_reflection(target, CallOptions::default(), reserved).is_err());
+    }
+}
+
+#[test]
+fn unary_call_with_json() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let target = crate::test_server::echo_server();
+    let entry = |key: &str, value: &str| MetadataEntry { key: key.to_owned(), value: MetadataValue::Text(value.to_owned()) };
+
+    let response = call_unary(target.clone(), CallOptions::default(), "echo.v1.Echo/Unary".to_owned(), r#"{"text": "hi", "code": 0}"#.to_owned(), vec![entry("x-tenant", "acme")], create_cancel_token()).unwrap();
+    assert_eq!(response.status, CallStatus { code: 0, name: "OK".to_owned(), ..CallStatus::default() });
+    let json: serde_json::Value = serde_json::from_str(response.response.as_deref().unwrap()).unwrap();
+    assert_eq!(json, serde_json::json!({ "text": "hi", "code": 0 }));
+    assert!(response.headers.contains(&entry("x-tenant", "acme")));
+    assert!(response.trailers.contains(&entry("grpc-status", "0")));
+
+    let metadata = vec![entry("echo-status", "5"), entry("echo-message", "no%20such%20echo")];
+    let response = call_unary(target.clone(), CallOptions::default(), "echo.v1.Echo.Unary".to_owned(), "{}".to_owned(), metadata, create_cancel_token()).unwrap();
+    assert_eq!(response.status, CallStatus { code: 5, name: "NOT_FOUND".to_owned(), message: "no such echo".to_owned(), details: Vec::new(), origin: StatusOrigin::Server });
+    assert_eq!(response.response, None);
+
+    let error = call_unary(target.clone(), CallOptions::default(), "/echo.v1.Echo/Unary".to_owned(), r#"{"txt": 1}"#.to_owned(), Vec::new(), create_cancel_token()).unwrap_err();
+    assert_eq!(error.to_string(), "invalid echo.v1.EchoMessage request: /txt: unknown field `txt` in echo.v1.EchoMessage");
+    // The echo of a request over the 4 MiB limit is refused as soon as its length is known.
+    let request = serde_json::json!({ "text": "a".repeat(5 << 20) }).to_string();
+    let status = call_unary(target.clone(), CallOptions::default(), "echo.v1.Echo/Unary".to_owned(), request, Vec::new(), create_cancel_token()).unwrap().status;
+    assert_eq!((status.name.as_str(), status.origin), ("RESOURCE_EXHAUSTED", StatusOrigin::Rejected));
+    for (method, message) in [("ServerStream", "returns a stream of responses"), ("Bidi", "takes a stream of requests")] {
+        let error = call_unary(target.clone(), CallOptions::default(), format!("echo.v1.Echo/{}", method), "{}".to_owned(), Vec::new(), create_cancel_token()).unwrap_err();
+        assert_eq!(error.to_string(), format!("`/echo.v1.Echo/{}` {}", method, message));
+    }
+}
+
+#[test]
+fn binary_metadata() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let target = crate::test_server::echo_server();
+    let text = |key: &str, value: &str| MetadataEntry { key: key.to_owned(), value: MetadataValue::Text(value.to_owned()) };
+    let binary = |key: &str, bytes: &[u8]| MetadataEntry { key: key.to_owned(), value: MetadataValue::Binary(bytes.to_vec()) };
+    let call = |metadata| call_unary(target.clone(), CallOptions::default(), "echo.v1.Echo/Unary".to_owned(), "{}".to_owned(), metadata, create_cancel_token());
+
+    // A google.rpc.Status with code 5.
+    let metadata = vec![binary("X-Trace-Bin", &[0, 0xff]), binary("echo-details-bin", &[8, 5]), text("echo-status", "5")];
+    let response = call(metadata).unwrap();
+    assert!(response.headers.contains(&binary("x-trace-bin", &[0, 0xff])));
+    assert!(response.trailers.contains(&binary("grpc-status-details-bin", &[8, 5])));
+
+    let error = call(vec![text("grpc-timeout", "1S")]).unwrap_err();
+    assert_eq!(error.to_string(), "`grpc-timeout` is reserved: keys starting with `grpc-` are for gRPC itself");
+    let problems = validate_metadata(vec![text("x-tenant", "acme"), text("x-trace-bin", "AAH+/w"), binary("x-tenant", &[1])]);
+    assert_eq!(problems.iter().map(|p| p.index).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(problems[0].message, "`x-trace-bin` takes a binary value");
+}
+
+#[test]
+fn rich_error_details() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let target = crate::test_server::echo_server();
+    let (_, pool) = schema::message_by_name("echo.v1.EchoMessage").unwrap();
+    let status_type = crate::bundled::pool().unwrap().message_by_name("google.rpc.Status").unwrap();
+    let status = text_format::parse(&status_type, r#"
+        code: 3
+        details { [type.googleapis.com/google.rpc.BadRequest] { field_violations { field: "text" description: "too long" } } }
+        details { [type.googleapis.com/echo.v1.EchoMessage] { text: "hi" } }
+        details { type_url: "type.googleapis.com/acme.Unknown" value: "\x01\x02" }
+    "#, &pool).unwrap();
+    let metadata = vec![
+        MetadataEntry { key: "echo-status".to_owned(), value: MetadataValue::Text("3".to_owned()) },
+        MetadataEntry { key: "echo-details-bin".to_owned(), value: MetadataValue::Binary(codec::encode(&*status).unwrap()) },
+    ];
+
+    let response = call_unary(target, CallOptions::default(), "echo.v1.Echo/Unary".to_owned(), "{}".to_owned(), metadata, create_cancel_token()).unwrap();
+    assert_eq!(response.status.name, "INVALID_ARGUMENT");
+    let details = &response.status.details;
+    let json = |i: usize| serde_json::from_str::<serde_json::Value>(details[i].json.as_deref().unwrap()).unwrap();
+    assert_eq!(details[0].type_url, "type.googleapis.com/google.rpc.BadRequest");
+    assert_eq!(json(0), serde_json::json!({ "fieldViolations": [{ "field": "text", "description": "too long" }] }));
+    assert_eq!(json(1), serde_json::json!({ "text": "hi" }));
+    assert_eq!((details[2].json.as_deref(), details[2].value.as_slice()), (None, &[1, 2][..]));
+}
+
+#[test]
+fn tls_and_client_certificates() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let target = crate::test_server::tls_echo_server(dir.path());
+    let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+    let pem = ClientCertificate::Pem { certificate_path: path("client.pem"), private_key_path: path("client.key") };
+    let call = |tls: TlsSettings| call_unary(target.clone(), CallOptions { tls: Some(tls), ..CallOptions::default() }, "echo.v1.Echo/Unary".to_owned(), r#"{"text": "hi"}"#.to_owned(), Vec::new(), create_cancel_token());
+    let trusted = TlsSettings { ca_certificate_path: Some(path("ca.pem")), client_certificate: Some(pem.clone()), authority: Some("grpc.internal:443".to_owned()), ..TlsSettings::default() };
+
+    let response = call(trusted.clone()).unwrap();
+    assert_eq!(response.status.code, 0);
+    assert!(response.headers.contains(&MetadataEntry { key: "echo-authority".to_owned(), value: MetadataValue::Text("grpc.internal:443".to_owned()) }));
+
+    let pkcs12 = ClientCertificate::Pkcs12 { path: path("client.p12"), password: "secret".to_owned() };
+    assert_eq!(call(TlsSettings { client_certificate: Some(pkcs12.clone()), ..trusted.clone() }).unwrap().status.code, 0);
+    let wrong_password = ClientCertificate::Pkcs12 { path: path("client.p12"), password: "guess".to_owned() };
+    assert!(call(TlsSettings { client_certificate: Some(wrong_password), ..trusted.clone() }).is_err());
+
+    // The certificate is for grpc.internal, not the target's address.
+    let error = call(TlsSettings { authority: None, ..trusted.clone() }).unwrap_err();
+    assert!(format!("{:#}", error).contains("TLS handshake"), "{:#}", error);
+    assert_eq!(call(TlsSettings { client_certificate: Some(pem), insecure_skip_verify: true, ..TlsSettings::default() }).unwrap().status.code, 0);
+
+    // The server requires a client certificate.
+    assert!(call(TlsSettings { client_certificate: None, ..trusted }).is_err());
+    assert!(call_unary(target, CallOptions::default(), "echo.v1.Echo/Unary".to_owned(), "{}".to_owned(), Vec::new(), create_cancel_token()).is_err());
+}
+
+#[test]
+fn deadlines_and_cancellation() {
+    use std::time::{Duration, Instant};
+
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let target = crate::test_server::echo_server();
+    let entry = |key: &str, value: &str| MetadataEntry { key: key.to_owned(), value: MetadataValue::Text(value.to_owned()) };
+    let timeout = |ms| CallOptions { timeout_ms: Some(ms), ..CallOptions::default() };
+    let call = |options, metadata, cancel| call_unary(target.clone(), options, "echo.v1.Echo/Unary".to_owned(), "{}".to_owned(), metadata, cancel).unwrap();
+
+    let response = call(timeout(5000), Vec::new(), create_cancel_token());
+    assert_eq!(response.status.code, 0);
+    let sent = response.headers.iter().find(|h| h.key == "echo-timeout").unwrap();
+    let MetadataValue::Text(sent) = &sent.value else { panic!("expected text, got {:?}", sent) };
+    let micros: u32 = sent.strip_suffix('u').unwrap().parse().unwrap();
+    assert!((4_000_000..=5_000_000).contains(&micros), "{}", sent);
+    assert!(call(CallOptions::default(), Vec::new(), create_cancel_token()).headers.iter().all(|h| h.key != "echo-timeout"));
+
+    let started = Instant::now();
+    let status = call(timeout(100), vec![entry("echo-stall-ms", "5000")], create_cancel_token()).status;
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!((status.name.as_str(), status.origin), ("DEADLINE_EXCEEDED", StatusOrigin::Deadline));
+    assert_eq!(status.message, "no response within the 100ms deadline");
+
+    let status = call(timeout(5000), vec![entry("echo-status", "4")], create_cancel_token()).status;
+    assert_eq!((status.name.as_str(), status.origin), ("DEADLINE_EXCEEDED", StatusOrigin::Server));
+
+    let token = create_cancel_token();
+    let canceller = token.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        cancel(canceller);
+    });
+    let status = call(CallOptions::default(), vec![entry("echo-stall-ms", "5000")], token).status;
+    assert_eq!((status.name.as_str(), status.origin), ("CANCELLED", StatusOrigin::Cancelled));
+
+    // The deadline passes while the server waits before sending the second message.
+    let events = std::sync::Mutex::new(Vec::new());
+    let emit = |event: call::Event| {
+        events.lock().unwrap().push(CallEvent::from(event));
+        true
+    };
+    let metadata = metadata::to_header_map(&[entry("echo-delay-ms", "5000")]).unwrap();
+    runtime().block_on(call::server_streaming(&target, &timeout(300), "echo.v1.Echo/ServerStream", "{}", &metadata, &CancelToken::default(), &emit)).unwrap();
+    let events = events.into_inner().unwrap();
+    assert_eq!(events.len(), 3);
+    assert!(matches!(&events[1], CallEvent::Message { .. }));
+    assert!(matches!(&events[2], CallEvent::Finished { status, .. } if status.origin == StatusOrigin::Deadline));
+}
+
+#[test]
+fn compressed_messages() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let target = crate::test_server::echo_server();
+    let text = "hello ".repeat(50);
+    let request = serde_json::json!({ "text": text }).to_string();
+    let call = |compression| call_unary(target.clone(), CallOptions { compression, ..CallOptions::default() }, "echo.v1.Echo/Unary".to_owned(), request.clone(), Vec::new(), create_cancel_token()).unwrap();
+
+    let response = call(Compression::Identity);
+    let size = response.request_size.unwrap();
+    assert_eq!((size.compressed, size.uncompressed), (303, 303));
+    assert_eq!(response.response_size, Some(size));
+    let accepted = MetadataEntry { key: "echo-accept-encoding".to_owned(), value: MetadataValue::Text("identity,gzip,deflate,zstd".to_owned()) };
+    assert!(response.headers.contains(&accepted));
+    assert!(response.headers.iter().all(|h| h.key != "grpc-encoding"));
+
+    for compression in [Compression::Gzip, Compression::Deflate, Compression::Zstd] {
+        let response = call(compression);
+        assert_eq!(response.status.code, 0, "{:?}: {}", compression, response.status.message);
+        let json: serde_json::Value = serde_json::from_str(response.response.as_deref().unwrap()).unwrap();
+        assert_eq!(json["text"], text.as_str());
+        let size = response.request_size.unwrap();
+        assert_eq!(size.uncompressed, 303);
+        assert!(size.compressed < 50, "{:?}: {:?}", compression, size);
+        // The server echoes the compressed message as it was sent.
+        assert_eq!(response.response_size, Some(size));
+    }
+}
+
+#[test]
+fn server_streaming_call() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let target = crate::test_server::echo_server();
+    let entry = |key: &str, value: &str| MetadataEntry { key: key.to_owned(), value: MetadataValue::Text(value.to_owned()) };
+    let stream = |metadata: Vec<MetadataEntry>, cancel_after: usize| {
+        let cancel = CancelToken::default();
+        let events = std::sync::Mutex::new(Vec::new());
+        let emit = |event: call::Event| {
+            let mut events = events.lock().unwrap();
+            events.push(CallEvent::from(event));
+            if events.len() == cancel_after {
+                cancel.cancel();
+            }
+            true
+        };
+        let metadata = metadata::to_header_map(&metadata).unwrap();
+        runtime().block_on(call::server_streaming(&target, &CallOptions::default(), "echo.v1.Echo/ServerStream", r#"{"id": 7}"#, &metadata, &cancel, &emit)).unwrap();
+        events.into_inner().unwrap()
+    };
+
+    let events = stream(vec![entry("echo-repeat", "2")], 0);
+    assert_eq!(events.len(), 4);
+    assert!(matches!(&events[0], CallEvent::Headers(_)));
+    let CallEvent::Message { json, received_at, size } = &events[1] else { panic!("expected a message, got {:?}", events[1]) };
+    assert_eq!(json.replace(char::is_whitespace, ""), r#"{"id":"7"}"#);
+    assert_eq!(*size, MessageSize { compressed: 2, uncompressed: 2 });
+    assert!(*received_at > 1_600_000_000_000_000);
+    assert!(matches!(&events[2], CallEvent::Message { .. }));
+    let CallEvent::Finished { status, trailers } = &events[3] else { panic!("expected the end of the call, got {:?}", events[3]) };
+    assert_eq!(status.name, "OK");
+    assert!(trailers.contains(&entry("grpc-status", "0")));
+
+    // Cancelled while the server waits before sending the second message.
+    let events = stream(vec![entry("echo-delay-ms", "5000")], 2);
+    assert_eq!(events.len(), 3);
+    assert!(matches!(&events[1], CallEvent::Message { .. }));
+    assert!(matches!(&events[2], CallEvent::Cancelled));
+}
+
+#[test]
+fn streaming_sessions() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let target = crate::test_server::echo_server();
+    let open = |method: &str| {
+        let session = std::sync::Arc::new(runtime().block_on(CallSession::open(&target, &CallOptions::default(), method, &http::HeaderMap::new())).unwrap());
+        let (events, received) = mpsc::channel();
+        let receiver = session.clone();
+        std::thread::spawn(move || runtime().block_on(receiver.receive(&|event| events.send(CallEvent::from(event)).is_ok())).unwrap());
+        (session, move || received.recv_timeout(Duration::from_secs(5)).unwrap())
+    };
+    let message = |event: CallEvent| match event {
+        CallEvent::Message { json, .. } => json.replace(char::is_whitespace, ""),
+        event => panic!("expected a message, got {:?}", event),
+    };
+
+    // Every request is answered before the next one is sent.
+    let (session, next) = open("echo.v1.Echo/Bidi");
+    assert!(matches!(next(), CallEvent::Headers(_)));
+    session.send(r#"{"text": "one"}"#).unwrap();
+    assert_eq!(message(next()), r#"{"text":"one"}"#);
+    session.send(r#"{"text": "two"}"#).unwrap();
+    assert_eq!(message(next()), r#"{"text":"two"}"#);
+    session.half_close().unwrap();
+    assert!(matches!(next(), CallEvent::Finished { status, .. } if status.code == 0));
+    assert_eq!(session.send("{}").unwrap_err().to_string(), "no more requests can be sent");
+
+    let (session, next) = open("echo.v1.Echo.ClientStream");
+    session.send(r#"{"count": 1}"#).unwrap();
+    session.send(r#"{"count": 2}"#).unwrap();
+    session.half_close().unwrap();
+    assert!(matches!(next(), CallEvent::Headers(_)));
+    assert_eq!(message(next()), r#"{"count":2}"#);
+    assert!(matches!(next(), CallEvent::Finished { .. }));
+
+    let (session, next) = open("echo.v1.Echo/Bidi");
+    assert!(matches!(next(), CallEvent::Headers(_)));
+    session.cancel();
+    assert!(matches!(next(), CallEvent::Cancelled));
+    let error = runtime().block_on(session.receive(&|_| true)).unwrap_err();
+    assert_eq!(error.to_string(), "the call was cancelled");
+
+    let error = runtime().block_on(CallSession::open(&target, &CallOptions::default(), "echo.v1.Echo/Unary", &http::HeaderMap::new())).err().unwrap();
+    assert_eq!(error.to_string(), "`/echo.v1.Echo/Unary` takes a single request");
+}
+
+#[test]
+fn decode_payloads() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    // `text` and `child.id`, an unknown length-delimited field 100 in `child` and an
+    // unknown varint 99 at the top.
+    let hex = "0a026869 7207 1005 a206026162 98069601";
+    let expected_unknown = vec![
+        UnknownField { path: String::new(), number: 99, wire_type: WireType::Varint, value: "150".to_owned() },
+        UnknownField { path: "/child".to_owned(), number: 100, wire_type: WireType::LengthDelimited, value: "YWI=".to_owned() },
+    ];
+
+    let decoded = decode_message("echo.v1.EchoMessage".to_owned(), hex.to_owned(), PayloadEncoding::Hex).unwrap();
+    assert_eq!(decoded.json.replace(char::is_whitespace, ""), r#"{"text":"hi","child":{"id":"5"}}"#);
+    assert_eq!(decoded.unknown_fields, expected_unknown);
+
+    let bytes = decode::parse_hex(hex).unwrap();
+    let base64 = json::encode_base64(&bytes);
+    let decoded = decode_message(".echo.v1.EchoMessage".to_owned(), base64, PayloadEncoding::Base64).unwrap();
+    assert_eq!(decoded.unknown_fields, expected_unknown);
+
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), &bytes).unwrap();
+    let decoded = decode_message_file("echo.v1.EchoMessage".to_owned(), file.path().to_str().unwrap().to_owned()).unwrap();
+    assert_eq!(decoded.unknown_fields.len(), 2);
+
+    let error = decode_message("echo.v1.EchoMessage".to_owned(), "0a05".to_owned(), PayloadEncoding::Hex).unwrap_err();
+    assert_eq!(error.to_string(), "payload is not a valid echo.v1.EchoMessage");
+}
+
+#[test]
+fn guess_raw_message_type() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    // text: "hi", id: 7, child: { count: 3, numbers: [1, 2] }, labels: { 9: "x" }.
+    let raw = decode_raw("0a026869 1007 7206 1803 4a020102 aa01050809120178".to_owned(), PayloadEncoding::Hex, true).unwrap();
+    assert_eq!(raw.fields.len(), 4);
+    let best = &raw.type_guesses[0];
+    assert_eq!((best.message_name.as_str(), best.matched_fields, best.total_fields), ("echo.v1.EchoMessage", 8, 8));
+
+    let raw = decode_raw("CgJoaQ".to_owned(), PayloadEncoding::Base64, false).unwrap();
+    assert_eq!(raw.fields[0].value, RawValue::String("hi".to_owned()));
+    assert!(raw.type_guesses.is_empty());
+}
+
+#[test]
+fn convert_between_formats() {
+    load_proto_from_files(vec!["testdata/echo/echo.proto".to_owned()], Workspace::default()).unwrap();
+    let convert = |input: &str, from, to| convert_message("echo.v1.EchoMessage".to_owned(), input.to_owned(), from, to).unwrap();
+
+    let hex = convert("text: \"hi\"\nid: 7", MessageFormat::Text, MessageFormat::Hex).output.unwrap();
+    assert_eq!(hex, "0a0268691007");
+    let json = convert(&hex, MessageFormat::Hex, MessageFormat::Json).output.unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&json).unwrap(), serde_json::json!({ "text": "hi", "id": "7" }));
+    let base64 = convert(&json, MessageFormat::Json, MessageFormat::Base64).output.unwrap();
+    assert_eq!(convert(&base64, MessageFormat::Base64, MessageFormat::Text).output.unwrap(), "text: \"hi\"\nid: 7\n");
+
+    let error = convert("text: \"hi\"\nmood: SAD", MessageFormat::Text, MessageFormat::Json).error.unwrap();
+    assert_eq!((error.line, error.column), (Some(2), Some(7)));
+    assert_eq!(error.message, "`SAD` is not a value of enum echo.v1.EchoMessage.Mood");
+    let error = convert("{\n  \"text\": }", MessageFormat::Json, MessageFormat::Text).error.unwrap();
+    assert_eq!((error.line, error.column), (Some(2), Some(11)));
+    assert!(convert("{\"count\": -1}", MessageFormat::Json, MessageFormat::Text).error.unwrap().line.is_none());
+    let converted = convert(r#"{"at": "2023-01-01T00:00:0é"}"#, MessageFormat::Json, MessageFormat::Hex);
+    assert_eq!(converted.output, None);
+    assert!(converted.error.unwrap().message.contains("RFC 3339 timestamp"));
+    assert!(convert_message("echo.v1.Nope".to_owned(), String::new(), MessageFormat::Json, MessageFormat::Text).is_err());
 }
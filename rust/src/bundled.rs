@@ -0,0 +1,138 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+
+use crate::pool::DescriptorPool;
+
+/// `.proto` sources shipped with the library, keyed by their import path.
+pub const FILES: &[(&str, &str)] = &[
+    (
+        "grpc/reflection/v1/reflection.proto",
+        include_str!("../proto/grpc/reflection/v1/reflection.proto"),
+    ),
+    (
+        "grpc/reflection/v1alpha/reflection.proto",
+        include_str!("../proto/grpc/reflection/v1alpha/reflection.proto"),
+    ),
+    (
+        "google/protobuf/any.proto",
+        include_str!("../proto/google/protobuf/any.proto"),
+    ),
+    (
+        "google/protobuf/api.proto",
+        include_str!("../proto/google/protobuf/api.proto"),
+    ),
+    (
+        "google/protobuf/descriptor.proto",
+        include_str!("../proto/google/protobuf/descriptor.proto"),
+    ),
+    (
+        "google/protobuf/duration.proto",
+        include_str!("../proto/google/protobuf/duration.proto"),
+    ),
+    (
+        "google/protobuf/empty.proto",
+        include_str!("../proto/google/protobuf/empty.proto"),
+    ),
+    (
+        "google/protobuf/field_mask.proto",
+        include_str!("../proto/google/protobuf/field_mask.proto"),
+    ),
+    (
+        "google/protobuf/source_context.proto",
+        include_str!("../proto/google/protobuf/source_context.proto"),
+    ),
+    (
+        "google/protobuf/struct.proto",
+        include_str!("../proto/google/protobuf/struct.proto"),
+    ),
+    (
+        "google/protobuf/timestamp.proto",
+        include_str!("../proto/google/protobuf/timestamp.proto"),
+    ),
+    (
+        "google/protobuf/type.proto",
+        include_str!("../proto/google/protobuf/type.proto"),
+    ),
+    (
+        "google/protobuf/wrappers.proto",
+        include_str!("../proto/google/protobuf/wrappers.proto"),
+    ),
+    (
+        "google/rpc/error_details.proto",
+        include_str!("../proto/google/rpc/error_details.proto"),
+    ),
+    (
+        "google/rpc/status.proto",
+        include_str!("../proto/google/rpc/status.proto"),
+    ),
+];
+
+/// Directory holding the bundled sources, written out on first use.
+///
+/// Parsers only understand include directories, so the bundled files are added as the
+/// last include root and act as a fallback for anything the user's roots don't provide.
+///
+/// Each process gets its own private directory, so other processes can't change the files
+/// under it. They are written next to it first and only moved in place once complete.
+pub fn include_dir() -> Result<PathBuf> {
+    static DIR: OnceLock<Result<PathBuf, String>> = OnceLock::new();
+
+    DIR.get_or_init(|| {
+        let write = || -> std::io::Result<PathBuf> {
+            let root = tempfile::Builder::new()
+                .prefix("grpc_debug-")
+                .tempdir()?
+                .keep();
+            let partial = root.join("include.partial");
+            for (name, content) in FILES {
+                let path = partial.join(name);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(&path, content)?;
+            }
+            let dir = root.join("include");
+            fs::rename(&partial, &dir)?;
+
+            Ok(dir)
+        };
+        write().map_err(|e| e.to_string())
+    })
+    .clone()
+    .map_err(|e| anyhow!("failed to write bundled protos: {}", e))
+}
+
+/// Every bundled file, parsed and linked.
+///
+/// Used for the messages the library itself exchanges with servers, such as those of the
+/// reflection service.
+pub fn pool() -> Result<&'static DescriptorPool> {
+    static POOL: OnceLock<Result<DescriptorPool, String>> = OnceLock::new();
+
+    POOL.get_or_init(|| {
+        let dir = include_dir().map_err(|e| e.to_string())?;
+        let parsed = protobuf_parse::Parser::new()
+            .pure()
+            .include(&dir)
+            .inputs(FILES.iter().map(|(name, _)| dir.join(name)))
+            .parse_and_typecheck()
+            .map_err(|e| format!("{:#}", e))?;
+
+        DescriptorPool::new(parsed.file_descriptors).map_err(|e| format!("{:#}", e))
+    })
+    .as_ref()
+    .map_err(|e| anyhow!("failed to load bundled protos: {}", e))
+}
+
+#[test]
+fn include_dir_holds_every_file() {
+    let dir = include_dir().unwrap();
+    assert_eq!(include_dir().unwrap(), dir);
+    for (name, content) in FILES {
+        assert_eq!(fs::read_to_string(dir.join(name)).unwrap(), *content);
+    }
+    assert!(!dir.with_file_name("include.partial").exists());
+}
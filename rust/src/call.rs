@@ -0,0 +1,488 @@
+#![allow(dead_code)]
+
+//! Calls to methods of loaded schemas, with messages given and returned as JSON.
+
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use http::HeaderMap;
+use protobuf::reflect::{MessageDescriptor, MethodDescriptor, ReflectValueRef};
+
+use crate::api::{CallOptions, MessageSize, StatusDetail};
+use crate::bundled;
+use crate::cancel::CancelToken;
+use crate::codec;
+use crate::grpc::{Channel, Message, Receiver, Sender, Status};
+use crate::json;
+use crate::pool::DescriptorPool;
+use crate::schema;
+
+/// A method of a loaded schema, resolved once per call.
+pub struct Method {
+    /// Request path, `/package.Service/Method`.
+    pub path: String,
+    pub descriptor: MethodDescriptor,
+    pool: Arc<DescriptorPool>,
+}
+
+impl Method {
+    /// Look up `name`, `package.Service/Method` or `package.Service.Method`.
+    pub fn find(name: &str) -> Result<Self> {
+        let (path, descriptor, pool) = schema::method_by_name(name)?;
+
+        Ok(Method {
+            path,
+            descriptor,
+            pool,
+        })
+    }
+
+    /// Encode a request given as JSON.
+    pub fn encode_request(&self, json: &str) -> Result<Vec<u8>> {
+        let input = self.descriptor.input_type();
+        let message = json::parse(&input, json, &self.pool)
+            .map_err(|e| anyhow!("invalid {} request: {}", input.full_name(), e))?;
+
+        codec::encode(&*message)
+    }
+
+    /// Decode a response into pretty-printed JSON.
+    pub fn decode_response(&self, bytes: &[u8]) -> Result<String> {
+        let output = self.descriptor.output_type();
+        let message = output
+            .parse_from_bytes(bytes)
+            .with_context(|| format!("server sent an invalid {}", output.full_name()))?;
+
+        json::print(&*message, &self.pool, true)
+            .map_err(|e| anyhow!("failed to print {}: {}", output.full_name(), e))
+    }
+}
+
+/// The details of `status`, a serialized `google.rpc.Status`, each decoded as the loaded or
+/// bundled message its type URL names.
+///
+/// A detail whose type is unknown only has its type URL and bytes; a status that isn't a
+/// `google.rpc.Status` has no details, its bytes staying in the trailers.
+pub fn status_details(status: &[u8]) -> Vec<StatusDetail> {
+    let Ok(bundled) = bundled::pool() else {
+        return Vec::new();
+    };
+    let descriptor = bundled.message_by_name("google.rpc.Status").unwrap();
+    let Ok(status) = descriptor.parse_from_bytes(status) else {
+        return Vec::new();
+    };
+
+    let details = descriptor
+        .field_by_name("details")
+        .unwrap()
+        .get_repeated(&*status);
+    details
+        .into_iter()
+        .map(|any| {
+            let ReflectValueRef::Message(any) = any else {
+                unreachable!("details are Any messages")
+            };
+            let get = |name| {
+                any.descriptor_dyn()
+                    .field_by_name(name)
+                    .unwrap()
+                    .get_singular_field_or_default(&*any)
+            };
+            let type_url = get("type_url").to_str().unwrap().to_owned();
+            let value = get("value").to_bytes().unwrap().to_vec();
+            StatusDetail {
+                json: detail_json(&type_url, &value),
+                type_url,
+                value,
+            }
+        })
+        .collect()
+}
+
+fn detail_json(type_url: &str, value: &[u8]) -> Option<String> {
+    let name = type_url.rsplit('/').next()?;
+    let print = |descriptor: MessageDescriptor, pool: &DescriptorPool| {
+        let message = descriptor.parse_from_bytes(value).ok()?;
+        json::print(&*message, pool, true).ok()
+    };
+
+    match schema::message_by_name(name) {
+        Ok((descriptor, pool)) => print(descriptor, &pool),
+        Err(_) => {
+            let bundled = bundled::pool().ok()?;
+            print(bundled.message_by_name(name)?, bundled)
+        }
+    }
+}
+
+/// Outcome of a unary call.
+pub struct UnaryResponse {
+    /// The response message as JSON, absent when the call failed.
+    pub message: Option<String>,
+    pub status: Status,
+    pub headers: HeaderMap,
+    pub trailers: HeaderMap,
+    /// Size of the request, absent when it wasn't sent.
+    pub request_size: Option<MessageSize>,
+    /// Size of the response, absent when none was received.
+    pub response_size: Option<MessageSize>,
+}
+
+/// When a call started with `options` must be over by.
+struct Deadline {
+    timeout: Duration,
+    at: Instant,
+}
+
+impl Deadline {
+    fn new(options: &CallOptions) -> Option<Self> {
+        let timeout = Duration::from_millis(options.timeout_ms?.into());
+        Some(Deadline {
+            timeout,
+            at: Instant::now() + timeout,
+        })
+    }
+}
+
+/// Wait until `deadline` passes, forever if there is none, and return the status the call
+/// then ends with.
+async fn expired(deadline: &Option<Deadline>) -> Status {
+    match deadline {
+        Some(deadline) => {
+            tokio::time::sleep_until(deadline.at.into()).await;
+            Status::deadline_exceeded(deadline.timeout)
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Send `json` to the unary method `method` of the server at `target`.
+///
+/// The call failing on the server is a successful outcome: its status is in the response.
+/// So is the call outliving the deadline of `options` or being cancelled with `cancel`,
+/// which resets it.
+pub async fn unary(
+    target: &str,
+    options: &CallOptions,
+    method: &str,
+    json: &str,
+    metadata: &HeaderMap,
+    cancel: &CancelToken,
+) -> Result<UnaryResponse> {
+    let method = Method::find(method)?;
+    if method.descriptor.proto().client_streaming() {
+        bail!("`{}` takes a stream of requests", method.path);
+    }
+    if method.descriptor.proto().server_streaming() {
+        bail!("`{}` returns a stream of responses", method.path);
+    }
+    let request = method.encode_request(json)?;
+    let deadline = Deadline::new(options);
+
+    let ended = |status| UnaryResponse {
+        message: None,
+        status,
+        headers: HeaderMap::new(),
+        trailers: HeaderMap::new(),
+        request_size: None,
+        response_size: None,
+    };
+    tokio::select! {
+        result = run_unary(target, options, &method, &request, metadata, &deadline) => result,
+        status = expired(&deadline) => Ok(ended(status)),
+        () = cancel.cancelled() => Ok(ended(Status::cancelled())),
+    }
+}
+
+async fn run_unary(
+    target: &str,
+    options: &CallOptions,
+    method: &Method,
+    request: &[u8],
+    metadata: &HeaderMap,
+    deadline: &Option<Deadline>,
+) -> Result<UnaryResponse> {
+    let channel = Channel::connect(target, options.tls.as_ref()).await?;
+    let (mut sender, mut receiver) = channel
+        .call(
+            &method.path,
+            metadata,
+            deadline.as_ref().map(|d| d.at),
+            options.compression,
+        )
+        .await?;
+    // The server may answer before reading the request, e.g. with UNIMPLEMENTED, so a
+    // failed send is only reported when there is no status to explain it.
+    let sent = sender
+        .send(request)
+        .and_then(|size| sender.close().map(|()| size));
+    let request_size = sent.as_ref().ok().copied();
+
+    let headers = match receiver.headers().await {
+        Ok(headers) => headers.clone(),
+        Err(e) => return Err(sent.err().unwrap_or(e)),
+    };
+    let response: Option<Message> = receiver.message().await?;
+    let response_size = response.as_ref().map(|r| r.size);
+    let status = receiver.status().await?;
+    let trailers = receiver.trailers().cloned().unwrap_or_default();
+    if !status.is_ok() {
+        return Ok(UnaryResponse {
+            message: None,
+            status,
+            headers,
+            trailers,
+            request_size,
+            response_size,
+        });
+    }
+    sent?;
+
+    let message = response
+        .context("server ended the call without sending a response")
+        .and_then(|r| method.decode_response(&r.bytes))?;
+
+    Ok(UnaryResponse {
+        message: Some(message),
+        status,
+        headers,
+        trailers,
+        request_size,
+        response_size,
+    })
+}
+
+/// Progress of a streaming call.
+pub enum Event {
+    Headers(HeaderMap),
+    /// A response message as JSON.
+    Message {
+        json: String,
+        received_at: SystemTime,
+        size: MessageSize,
+    },
+    /// The call ended, on the server or when its deadline passed.
+    Finished {
+        status: Status,
+        trailers: HeaderMap,
+    },
+    /// The call was cancelled before the server finished it.
+    Cancelled,
+}
+
+/// Send `json` to the server-streaming method `method` of the server at `target` and report
+/// the responses through `emit` as they arrive.
+///
+/// Cancelling `cancel` resets the call, as does its deadline passing, which is reported as
+/// the status it finished with. So does `emit` returning `false`, once nobody is listening
+/// anymore.
+pub async fn server_streaming(
+    target: &str,
+    options: &CallOptions,
+    method: &str,
+    json: &str,
+    metadata: &HeaderMap,
+    cancel: &CancelToken,
+    emit: &dyn Fn(Event) -> bool,
+) -> Result<()> {
+    let method = Method::find(method)?;
+    if method.descriptor.proto().client_streaming() {
+        bail!("`{}` takes a stream of requests", method.path);
+    }
+    let request = method.encode_request(json)?;
+    let deadline = Deadline::new(options);
+
+    tokio::select! {
+        result = async {
+            let channel = Channel::connect(target, options.tls.as_ref()).await?;
+            let (mut sender, mut receiver) = channel
+                .call(
+                    &method.path,
+                    metadata,
+                    deadline.as_ref().map(|d| d.at),
+                    options.compression,
+                )
+                .await?;
+            let sent = sender.send(&request).and_then(|_| sender.close());
+            receive(&method, &mut receiver, sent, emit).await
+        } => result,
+        status = expired(&deadline) => {
+            emit(Event::Finished { status, trailers: HeaderMap::new() });
+            Ok(())
+        }
+        () = cancel.cancelled() => {
+            emit(Event::Cancelled);
+            Ok(())
+        }
+    }
+}
+
+/// Report the responses of a call whose requests have all been sent, `sent` telling whether
+/// sending them succeeded.
+async fn receive(
+    method: &Method,
+    receiver: &mut Receiver,
+    sent: Result<()>,
+    emit: &dyn Fn(Event) -> bool,
+) -> Result<()> {
+    let headers = match receiver.headers().await {
+        Ok(headers) => headers.clone(),
+        Err(e) => return Err(sent.err().unwrap_or(e)),
+    };
+    if !emit(Event::Headers(headers)) {
+        return Ok(());
+    }
+
+    while let Some(message) = receiver.message().await? {
+        let received_at = SystemTime::now();
+        let json = method.decode_response(&message.bytes)?;
+        let size = message.size;
+        if !emit(Event::Message {
+            json,
+            received_at,
+            size,
+        }) {
+            return Ok(());
+        }
+    }
+    let status = receiver.status().await?;
+    if status.is_ok() {
+        sent?;
+    }
+    emit(Event::Finished {
+        status,
+        trailers: receiver.trailers().cloned().unwrap_or_default(),
+    });
+
+    Ok(())
+}
+
+/// A call of a client-streaming or bidirectional streaming method, driven one message at a
+/// time.
+///
+/// Requests are sent with [`CallSession::send`] until [`CallSession::half_close`], while
+/// [`CallSession::receive`] reports the responses as they arrive.
+pub struct CallSession {
+    /// Descriptors are immutable once linked, but rust-protobuf doesn't mark them as
+    /// unwind-safe, which handles passed to Dart must be.
+    method: AssertUnwindSafe<Method>,
+    sender: Mutex<Option<Sender>>,
+    responses: Mutex<Responses>,
+    deadline: Option<Deadline>,
+    cancel: CancelToken,
+}
+
+/// Where the responses of a [`CallSession`] stand.
+enum Responses {
+    /// Waiting for [`CallSession::receive`].
+    Pending(Box<Receiver>),
+    Receiving,
+    /// Every response was reported, or the call was reset.
+    Ended,
+}
+
+impl CallSession {
+    /// Start a call of `method` on the server at `target`.
+    ///
+    /// The deadline of `options` runs from now, failing the call if it passes before the
+    /// server is reached.
+    pub async fn open(
+        target: &str,
+        options: &CallOptions,
+        method: &str,
+        metadata: &HeaderMap,
+    ) -> Result<Self> {
+        let method = Method::find(method)?;
+        if !method.descriptor.proto().client_streaming() {
+            bail!("`{}` takes a single request", method.path);
+        }
+        let deadline = Deadline::new(options);
+        let start = async {
+            let channel = Channel::connect(target, options.tls.as_ref()).await?;
+            channel
+                .call(
+                    &method.path,
+                    metadata,
+                    deadline.as_ref().map(|d| d.at),
+                    options.compression,
+                )
+                .await
+        };
+        let (sender, receiver) = tokio::select! {
+            result = start => result?,
+            status = expired(&deadline) => bail!("{}", status),
+        };
+
+        Ok(CallSession {
+            method: AssertUnwindSafe(method),
+            sender: Mutex::new(Some(sender)),
+            responses: Mutex::new(Responses::Pending(Box::new(receiver))),
+            deadline,
+            cancel: CancelToken::default(),
+        })
+    }
+
+    /// Send a request given as JSON, returning its size.
+    pub fn send(&self, json: &str) -> Result<MessageSize> {
+        let request = self.method.encode_request(json)?;
+        match self.sender.lock().unwrap().as_mut() {
+            Some(sender) => sender
+                .send(&request)
+                .context("the call ended before the request was sent"),
+            None => bail!("no more requests can be sent"),
+        }
+    }
+
+    /// Tell the server that no more requests will be sent.
+    pub fn half_close(&self) -> Result<()> {
+        match self.sender.lock().unwrap().take() {
+            Some(mut sender) => sender.close(),
+            None => bail!("requests were already closed"),
+        }
+    }
+
+    /// Report the responses through `emit` until the call ends, like [`server_streaming`]
+    /// does, resetting it if its deadline passes first. Only one receiver is allowed per
+    /// session.
+    pub async fn receive(&self, emit: &dyn Fn(Event) -> bool) -> Result<()> {
+        let mut receiver = {
+            let mut responses = self.responses.lock().unwrap();
+            match std::mem::replace(&mut *responses, Responses::Receiving) {
+                Responses::Pending(receiver) => receiver,
+                Responses::Receiving => bail!("responses are already being received"),
+                Responses::Ended => {
+                    *responses = Responses::Ended;
+                    if self.cancel.is_cancelled() {
+                        bail!("the call was cancelled");
+                    }
+                    bail!("the call has ended");
+                }
+            }
+        };
+
+        let result = tokio::select! {
+            result = receive(&self.method, &mut receiver, Ok(()), emit) => result,
+            status = expired(&self.deadline) => {
+                self.sender.lock().unwrap().take();
+                emit(Event::Finished { status, trailers: HeaderMap::new() });
+                Ok(())
+            }
+            () = self.cancel.cancelled() => {
+                emit(Event::Cancelled);
+                Ok(())
+            }
+        };
+        *self.responses.lock().unwrap() = Responses::Ended;
+
+        result
+    }
+
+    /// Reset the call, ending it on both sides.
+    pub fn cancel(&self) {
+        self.sender.lock().unwrap().take();
+        *self.responses.lock().unwrap() = Responses::Ended;
+        self.cancel.cancel();
+    }
+}
@@ -0,0 +1,174 @@
+#![allow(dead_code)]
+
+//! Binary encoding of dynamic messages.
+//!
+//! rust-protobuf drops every zero-valued singular field of a proto3 dynamic message when
+//! serializing it, including those with explicit presence (`optional` fields and oneof
+//! members), so a oneof set to `0` or `""` would never reach the server. Messages are
+//! written here instead, following the presence rules of the field's syntax.
+
+use anyhow::Result;
+use protobuf::descriptor::field_descriptor_proto::Type;
+use protobuf::reflect::{FieldDescriptor, ReflectFieldRef, ReflectValueRef, Syntax};
+use protobuf::rt::WireType;
+use protobuf::{CodedOutputStream, MessageDyn};
+
+pub fn encode(message: &dyn MessageDyn) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut os = CodedOutputStream::vec(&mut bytes);
+    write_message(&mut os, message)?;
+    os.flush()?;
+    drop(os);
+
+    Ok(bytes)
+}
+
+/// Whether a set field is written even when it holds its type's default value.
+pub fn has_presence(field: &FieldDescriptor) -> bool {
+    let proto = field.proto();
+    proto.proto3_optional()
+        || field.containing_oneof().is_some()
+        || matches!(proto.type_(), Type::TYPE_MESSAGE | Type::TYPE_GROUP)
+        || field.containing_message().file_descriptor().syntax() == Syntax::Proto2
+}
+
+/// Whether `value` is the default of its type, which proto3 doesn't write for fields
+/// without presence.
+pub fn is_default(value: &ReflectValueRef) -> bool {
+    match value {
+        ReflectValueRef::U32(v) => *v == 0,
+        ReflectValueRef::U64(v) => *v == 0,
+        ReflectValueRef::I32(v) => *v == 0,
+        ReflectValueRef::I64(v) => *v == 0,
+        // -0.0 isn't the default.
+        ReflectValueRef::F32(v) => v.to_bits() == 0,
+        ReflectValueRef::F64(v) => v.to_bits() == 0,
+        ReflectValueRef::Bool(v) => !*v,
+        ReflectValueRef::String(v) => v.is_empty(),
+        ReflectValueRef::Bytes(v) => v.is_empty(),
+        ReflectValueRef::Enum(_, v) => *v == 0,
+        ReflectValueRef::Message(_) => false,
+    }
+}
+
+fn is_packed(field: &FieldDescriptor) -> bool {
+    let packable = !matches!(
+        field.proto().type_(),
+        Type::TYPE_STRING | Type::TYPE_BYTES | Type::TYPE_MESSAGE | Type::TYPE_GROUP
+    );
+    let options = field.proto().options.get_or_default();
+    let syntax = field.containing_message().file_descriptor().syntax();
+
+    packable && options.packed.unwrap_or(syntax == Syntax::Proto3)
+}
+
+fn write_message(os: &mut CodedOutputStream, message: &dyn MessageDyn) -> Result<()> {
+    for field in message.descriptor_dyn().fields() {
+        let number = field.number() as u32;
+        let t = field.proto().type_();
+        match field.get_reflect(message) {
+            ReflectFieldRef::Optional(value) => {
+                if let Some(value) = value.value() {
+                    if has_presence(&field) || !is_default(&value) {
+                        write_value(os, number, t, &value)?;
+                    }
+                }
+            }
+            ReflectFieldRef::Repeated(values) if is_packed(&field) => {
+                if !values.is_empty() {
+                    let mut payload = Vec::new();
+                    let mut packed = CodedOutputStream::vec(&mut payload);
+                    for value in &values {
+                        write_value_no_tag(&mut packed, t, &value)?;
+                    }
+                    packed.flush()?;
+                    drop(packed);
+                    os.write_bytes(number, &payload)?;
+                }
+            }
+            ReflectFieldRef::Repeated(values) => {
+                for value in &values {
+                    write_value(os, number, t, &value)?;
+                }
+            }
+            ReflectFieldRef::Map(map) => {
+                let entry = field
+                    .containing_message()
+                    .nested_messages()
+                    .find(|m| m.full_name() == field.proto().type_name().trim_start_matches('.'))
+                    .unwrap();
+                let key_type = entry.field_by_number(1).unwrap().proto().type_();
+                let value_type = entry.field_by_number(2).unwrap().proto().type_();
+                for (key, value) in &map {
+                    let mut payload = Vec::new();
+                    let mut pair = CodedOutputStream::vec(&mut payload);
+                    write_value(&mut pair, 1, key_type, &key)?;
+                    write_value(&mut pair, 2, value_type, &value)?;
+                    pair.flush()?;
+                    drop(pair);
+                    os.write_bytes(number, &payload)?;
+                }
+            }
+        }
+    }
+    os.write_unknown_fields(message.special_fields_dyn().unknown_fields())?;
+
+    Ok(())
+}
+
+fn write_value(
+    os: &mut CodedOutputStream,
+    number: u32,
+    t: Type,
+    value: &ReflectValueRef,
+) -> Result<()> {
+    match t {
+        Type::TYPE_MESSAGE => {
+            let bytes = encode(&*value.to_message().unwrap())?;
+            os.write_bytes(number, &bytes)?;
+        }
+        Type::TYPE_GROUP => {
+            os.write_tag(number, WireType::StartGroup)?;
+            write_message(os, &*value.to_message().unwrap())?;
+            os.write_tag(number, WireType::EndGroup)?;
+        }
+        Type::TYPE_STRING => os.write_string(number, value.to_str().unwrap())?,
+        Type::TYPE_BYTES => os.write_bytes(number, value.to_bytes().unwrap())?,
+        _ => {
+            let wire_type = match t {
+                Type::TYPE_DOUBLE | Type::TYPE_FIXED64 | Type::TYPE_SFIXED64 => WireType::Fixed64,
+                Type::TYPE_FLOAT | Type::TYPE_FIXED32 | Type::TYPE_SFIXED32 => WireType::Fixed32,
+                _ => WireType::Varint,
+            };
+            os.write_tag(number, wire_type)?;
+            write_value_no_tag(os, t, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a scalar without its tag, as inside a packed field.
+fn write_value_no_tag(os: &mut CodedOutputStream, t: Type, value: &ReflectValueRef) -> Result<()> {
+    match t {
+        Type::TYPE_DOUBLE => os.write_double_no_tag(value.to_f64().unwrap())?,
+        Type::TYPE_FLOAT => os.write_float_no_tag(value.to_f32().unwrap())?,
+        Type::TYPE_INT64 => os.write_int64_no_tag(value.to_i64().unwrap())?,
+        Type::TYPE_UINT64 => os.write_uint64_no_tag(value.to_u64().unwrap())?,
+        Type::TYPE_INT32 => os.write_int32_no_tag(value.to_i32().unwrap())?,
+        Type::TYPE_FIXED64 => os.write_fixed64_no_tag(value.to_u64().unwrap())?,
+        Type::TYPE_FIXED32 => os.write_fixed32_no_tag(value.to_u32().unwrap())?,
+        Type::TYPE_BOOL => os.write_bool_no_tag(value.to_bool().unwrap())?,
+        Type::TYPE_UINT32 => os.write_uint32_no_tag(value.to_u32().unwrap())?,
+        Type::TYPE_ENUM => os.write_enum_no_tag(value.to_enum_value().unwrap())?,
+        Type::TYPE_SFIXED32 => os.write_sfixed32_no_tag(value.to_i32().unwrap())?,
+        Type::TYPE_SFIXED64 => os.write_sfixed64_no_tag(value.to_i64().unwrap())?,
+        Type::TYPE_SINT32 => os.write_sint32_no_tag(value.to_i32().unwrap())?,
+        Type::TYPE_SINT64 => os.write_sint64_no_tag(value.to_i64().unwrap())?,
+        Type::TYPE_STRING | Type::TYPE_BYTES | Type::TYPE_MESSAGE | Type::TYPE_GROUP => {
+            unreachable!("{:?} is not a scalar", t)
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+#![allow(dead_code)]
+
+//! Compression of messages, as announced in `grpc-encoding`.
+//!
+//! `deflate` is the zlib format, as in HTTP.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::api::Compression;
+
+/// Every encoding responses can be decoded from, for `grpc-accept-encoding`.
+pub const ACCEPTED: &str = "identity,gzip,deflate,zstd";
+
+/// Name of `compression` in `grpc-encoding`.
+pub fn name(compression: Compression) -> &'static str {
+    match compression {
+        Compression::Identity => "identity",
+        Compression::Gzip => "gzip",
+        Compression::Deflate => "deflate",
+        Compression::Zstd => "zstd",
+    }
+}
+
+pub fn from_name(name: &str) -> Option<Compression> {
+    match name {
+        "identity" => Some(Compression::Identity),
+        "gzip" => Some(Compression::Gzip),
+        "deflate" => Some(Compression::Deflate),
+        "zstd" => Some(Compression::Zstd),
+        _ => None,
+    }
+}
+
+pub fn compress(compression: Compression, message: &[u8]) -> Result<Vec<u8>> {
+    let level = flate2::Compression::default();
+    let compressed = match compression {
+        Compression::Identity => Ok(message.to_vec()),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            encoder.write_all(message).and_then(|()| encoder.finish())
+        }
+        Compression::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(message).and_then(|()| encoder.finish())
+        }
+        Compression::Zstd => zstd::encode_all(message, zstd::DEFAULT_COMPRESSION_LEVEL),
+    };
+
+    compressed.with_context(|| format!("failed to compress a message with {}", name(compression)))
+}
+
+/// Decompress `message`, failing once it grows past `limit` bytes.
+pub fn decompress(compression: Compression, message: &[u8], limit: usize) -> Result<Vec<u8>> {
+    // One byte over the limit is enough to know it is exceeded.
+    let take = limit as u64 + 1;
+    let mut decompressed = Vec::new();
+    let result = match compression {
+        Compression::Identity => message.take(take).read_to_end(&mut decompressed),
+        Compression::Gzip => GzDecoder::new(message)
+            .take(take)
+            .read_to_end(&mut decompressed),
+        Compression::Deflate => ZlibDecoder::new(message)
+            .take(take)
+            .read_to_end(&mut decompressed),
+        Compression::Zstd => zstd::stream::Decoder::new(message)
+            .and_then(|decoder| decoder.take(take).read_to_end(&mut decompressed)),
+    };
+    result.with_context(|| format!("received a message that isn't valid {}", name(compression)))?;
+    if decompressed.len() > limit {
+        bail!(
+            "received a message larger than the {} bytes allowed once decompressed",
+            limit
+        );
+    }
+
+    Ok(decompressed)
+}
+
+#[test]
+fn round_trip() {
+    let message = b"hello hello hello hello hello hello hello hello".repeat(10);
+    for compression in [
+        Compression::Identity,
+        Compression::Gzip,
+        Compression::Deflate,
+        Compression::Zstd,
+    ] {
+        let compressed = compress(compression, &message).unwrap();
+        assert_eq!(decompress(compression, &compressed, 1024).unwrap(), message);
+        assert_eq!(from_name(name(compression)), Some(compression));
+        if compression != Compression::Identity {
+            assert!(compressed.len() < message.len() / 4, "{:?}", compression);
+            assert!(
+                decompress(compression, &message, 1024).is_err(),
+                "{:?}",
+                compression
+            );
+            // 480 bytes expand past a limit of 100.
+            let error = decompress(compression, &compressed, 100).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "received a message larger than the 100 bytes allowed once decompressed"
+            );
+        }
+    }
+    assert_eq!(from_name("snappy"), None);
+}
@@ -0,0 +1,415 @@
+#![allow(dead_code)]
+
+//! Decoding of payloads captured outside of calls, e.g. copied from logs.
+
+use anyhow::{bail, Result};
+use protobuf::descriptor::field_descriptor_proto::Type;
+use protobuf::reflect::{
+    FieldDescriptor, MessageDescriptor, ReflectFieldRef, RuntimeFieldType, RuntimeType,
+};
+use protobuf::{MessageDyn, UnknownValueRef};
+
+use crate::api::{RawField, RawValue, TypeGuess, UnknownField, WireType};
+use crate::json;
+
+/// Parse hex digits, ignoring whitespace and an optional `0x` prefix.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let digits = digits.strip_prefix(b"0x").unwrap_or(&digits);
+    if !digits.len().is_multiple_of(2) {
+        bail!("hex payload has an odd number of digits");
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            // `from_str_radix` would also take a sign, e.g. `+f`.
+            if !pair.iter().all(u8::is_ascii_hexdigit) {
+                bail!("`{}` is not a hex byte", String::from_utf8_lossy(pair));
+            }
+            let pair = std::str::from_utf8(pair).unwrap();
+            Ok(u8::from_str_radix(pair, 16).unwrap())
+        })
+        .collect()
+}
+
+/// Parse base64, standard or URL-safe, ignoring whitespace.
+pub fn parse_base64(text: &str) -> Result<Vec<u8>> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    match json::decode_base64(&text) {
+        Some(bytes) => Ok(bytes),
+        None => bail!("payload is not valid base64"),
+    }
+}
+
+/// Unknown fields of `message` and of every message it contains, outermost first and by
+/// field number within a message.
+pub fn unknown_fields(message: &dyn MessageDyn) -> Vec<UnknownField> {
+    let mut fields = Vec::new();
+    collect_unknown_fields(message, "", &mut fields);
+
+    fields
+}
+
+fn collect_unknown_fields(message: &dyn MessageDyn, path: &str, fields: &mut Vec<UnknownField>) {
+    let mut unknown: Vec<UnknownField> = message
+        .special_fields_dyn()
+        .unknown_fields()
+        .iter()
+        .map(|(number, value)| {
+            let (wire_type, value) = match value {
+                UnknownValueRef::Varint(v) => (WireType::Varint, v.to_string()),
+                UnknownValueRef::Fixed64(v) => (WireType::Fixed64, v.to_string()),
+                UnknownValueRef::Fixed32(v) => (WireType::Fixed32, v.to_string()),
+                UnknownValueRef::LengthDelimited(v) => {
+                    (WireType::LengthDelimited, json::encode_base64(v))
+                }
+            };
+            UnknownField {
+                path: path.to_owned(),
+                number,
+                wire_type,
+                value,
+            }
+        })
+        .collect();
+    // Unknown fields are kept in a hash map.
+    unknown.sort_by_key(|f| f.number);
+    fields.extend(unknown);
+
+    for field in message.descriptor_dyn().fields() {
+        let field_path = json::pointer(path, field.json_name());
+        match field.get_reflect(message) {
+            ReflectFieldRef::Optional(value) => {
+                if let Some(m) = value.value().and_then(|v| v.to_message()) {
+                    collect_unknown_fields(&*m, &field_path, fields);
+                }
+            }
+            ReflectFieldRef::Repeated(values) => {
+                for (i, value) in values.into_iter().enumerate() {
+                    if let Some(m) = value.to_message() {
+                        let item_path = json::pointer(&field_path, &i.to_string());
+                        collect_unknown_fields(&*m, &item_path, fields);
+                    }
+                }
+            }
+            ReflectFieldRef::Map(map) => {
+                for (key, value) in &map {
+                    if let Some(m) = value.to_message() {
+                        let key = json::map_key_string(&key);
+                        let item_path = json::pointer(&field_path, &key);
+                        collect_unknown_fields(&*m, &item_path, fields);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Largest valid field number, 2^29 - 1.
+const MAX_FIELD_NUMBER: u64 = 536_870_911;
+
+/// Nesting beyond this is reported as an error rather than risking the stack.
+const MAX_DEPTH: usize = 100;
+
+/// Parse `bytes` as a message of unknown type, like `protoc --decode_raw` does.
+pub fn decode_raw(bytes: &[u8]) -> Result<Vec<RawField>> {
+    match parse_raw(bytes, 0, None, 0) {
+        Ok((fields, _)) => Ok(fields),
+        Err(e) => bail!("payload is not a protobuf message: {}", e),
+    }
+}
+
+/// Parse the fields in `bytes`, which start at byte `base` of the payload, up to its end or
+/// to the end of the group numbered `group`. Returns the fields and the number of bytes read.
+fn parse_raw(
+    bytes: &[u8],
+    base: usize,
+    group: Option<u32>,
+    depth: usize,
+) -> Result<(Vec<RawField>, usize), String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("messages nested more than {} deep", MAX_DEPTH));
+    }
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let start = pos;
+        let truncated = || format!("truncated field at byte {}", base + start);
+        let tag = read_varint(bytes, &mut pos).ok_or_else(truncated)?;
+        let number = tag >> 3;
+        if number == 0 || number > MAX_FIELD_NUMBER {
+            return Err(format!(
+                "invalid field number {} at byte {}",
+                number,
+                base + start
+            ));
+        }
+        let number = number as u32;
+        let (wire_type, value) = match tag & 7 {
+            0 => {
+                let value = read_varint(bytes, &mut pos).ok_or_else(truncated)?;
+                (WireType::Varint, RawValue::Varint(value))
+            }
+            1 => {
+                let value = take(bytes, &mut pos, 8).ok_or_else(truncated)?;
+                (
+                    WireType::Fixed64,
+                    RawValue::Fixed64(u64::from_le_bytes(value.try_into().unwrap())),
+                )
+            }
+            2 => {
+                let len = read_varint(bytes, &mut pos).ok_or_else(truncated)?;
+                let data_start = pos;
+                let data = usize::try_from(len)
+                    .ok()
+                    .and_then(|len| take(bytes, &mut pos, len))
+                    .ok_or_else(truncated)?;
+                (
+                    WireType::LengthDelimited,
+                    length_delimited(data, base + data_start, depth),
+                )
+            }
+            3 => {
+                let (group, len) = parse_raw(&bytes[pos..], base + pos, Some(number), depth + 1)?;
+                pos += len;
+                (WireType::StartGroup, RawValue::Group(group))
+            }
+            4 if group == Some(number) => return Ok((fields, pos)),
+            4 => {
+                return Err(format!(
+                    "unexpected end of group {} at byte {}",
+                    number,
+                    base + start
+                ))
+            }
+            5 => {
+                let value = take(bytes, &mut pos, 4).ok_or_else(truncated)?;
+                (
+                    WireType::Fixed32,
+                    RawValue::Fixed32(u32::from_le_bytes(value.try_into().unwrap())),
+                )
+            }
+            other => {
+                return Err(format!(
+                    "invalid wire type {} at byte {}",
+                    other,
+                    base + start
+                ))
+            }
+        };
+        fields.push(RawField {
+            number,
+            wire_type,
+            offset: (base + start) as u32,
+            length: (pos - start) as u32,
+            value,
+        });
+    }
+    if let Some(number) = group {
+        return Err(format!("group {} is not closed", number));
+    }
+
+    Ok((fields, pos))
+}
+
+/// Guess what a length-delimited value holds: text, then a nested message, then packed
+/// varints, and bytes when nothing else fits.
+fn length_delimited(data: &[u8], base: usize, depth: usize) -> RawValue {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if !text
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+        {
+            return RawValue::String(text.to_owned());
+        }
+    }
+    if let Ok((fields, _)) = parse_raw(data, base, None, depth + 1) {
+        return RawValue::Message(fields);
+    }
+    let mut pos = 0;
+    let mut values = Vec::new();
+    while let Some(value) = read_varint(data, &mut pos) {
+        values.push(value);
+    }
+    if pos == data.len() {
+        return RawValue::PackedVarints(values);
+    }
+
+    RawValue::Bytes(data.to_vec())
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let byte = *bytes.get(*pos + i)?;
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte < 0x80 {
+            *pos += i + 1;
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let data = bytes.get(*pos..pos.checked_add(len)?)?;
+    *pos += len;
+
+    Some(data)
+}
+
+/// Rank `candidates` by how well `fields` fit them: the share of fields, nested ones
+/// included, whose number is declared with a matching wire type. Only candidates matching
+/// at least one field are kept, best first.
+pub fn guess_types(fields: &[RawField], candidates: &[MessageDescriptor]) -> Vec<TypeGuess> {
+    let mut guesses: Vec<(TypeGuess, f64)> = candidates
+        .iter()
+        .filter_map(|descriptor| {
+            let (matched, total) = fit(fields, descriptor, 0);
+            if matched == 0 {
+                return None;
+            }
+            // Among equally good fits, prefer the message whose fields are mostly used.
+            let mut numbers: Vec<u32> = fields.iter().map(|f| f.number).collect();
+            numbers.sort();
+            numbers.dedup();
+            let declared = descriptor.fields().count().max(1);
+            let used = numbers
+                .iter()
+                .filter(|n| descriptor.field_by_number(**n).is_some())
+                .count();
+            let guess = TypeGuess {
+                message_name: descriptor.full_name().to_owned(),
+                score: matched as f64 / total as f64,
+                matched_fields: matched,
+                total_fields: total,
+            };
+            Some((guess, used as f64 / declared as f64))
+        })
+        .collect();
+    guesses.sort_by(|(a, a_used), (b, b_used)| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b_used.total_cmp(a_used))
+            .then_with(|| a.message_name.cmp(&b.message_name))
+    });
+
+    guesses.into_iter().map(|(guess, _)| guess).collect()
+}
+
+/// Fields of `fields` matching `descriptor`, and the number of fields looked at.
+fn fit(fields: &[RawField], descriptor: &MessageDescriptor, depth: usize) -> (u32, u32) {
+    let (mut matched, mut total) = (0, 0);
+    for raw in fields {
+        total += 1;
+        let Some(field) = descriptor.field_by_number(raw.number) else {
+            continue;
+        };
+        if !wire_type_fits(&field, raw.wire_type) {
+            continue;
+        }
+        matched += 1;
+
+        let nested = match &raw.value {
+            RawValue::Message(nested) | RawValue::Group(nested) => nested,
+            _ => continue,
+        };
+        if let Some(message) = message_type(&field) {
+            if depth < MAX_DEPTH {
+                let (m, t) = fit(nested, &message, depth + 1);
+                matched += m;
+                total += t;
+            }
+        }
+    }
+
+    (matched, total)
+}
+
+fn wire_type_fits(field: &FieldDescriptor, wire_type: WireType) -> bool {
+    let expected = match field.proto().type_() {
+        Type::TYPE_DOUBLE | Type::TYPE_FIXED64 | Type::TYPE_SFIXED64 => WireType::Fixed64,
+        Type::TYPE_FLOAT | Type::TYPE_FIXED32 | Type::TYPE_SFIXED32 => WireType::Fixed32,
+        Type::TYPE_STRING | Type::TYPE_BYTES | Type::TYPE_MESSAGE => WireType::LengthDelimited,
+        Type::TYPE_GROUP => WireType::StartGroup,
+        _ => WireType::Varint,
+    };
+    // Repeated scalars may be packed whatever the declaration says.
+    let packed = field.is_repeated()
+        && wire_type == WireType::LengthDelimited
+        && matches!(
+            expected,
+            WireType::Varint | WireType::Fixed32 | WireType::Fixed64
+        );
+
+    wire_type == expected || packed
+}
+
+/// The message type of `field`, or the entry type of a map field.
+fn message_type(field: &FieldDescriptor) -> Option<MessageDescriptor> {
+    match field.runtime_field_type() {
+        RuntimeFieldType::Singular(RuntimeType::Message(m))
+        | RuntimeFieldType::Repeated(RuntimeType::Message(m)) => Some(m),
+        RuntimeFieldType::Map(..) => {
+            let entry = field.proto().type_name().trim_start_matches('.');
+            field
+                .containing_message()
+                .nested_messages()
+                .find(|m| m.full_name() == entry)
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn payload_text_formats() {
+    assert_eq!(parse_hex("0x0a 02\n6869").unwrap(), b"\n\x02hi");
+    assert_eq!(
+        parse_hex("abc").unwrap_err().to_string(),
+        "hex payload has an odd number of digits"
+    );
+    assert_eq!(
+        parse_hex("zz").unwrap_err().to_string(),
+        "`zz` is not a hex byte"
+    );
+    assert_eq!(
+        parse_hex("0x+f").unwrap_err().to_string(),
+        "`+f` is not a hex byte"
+    );
+    assert!(parse_hex("+1").is_err());
+    assert_eq!(parse_base64("CgJo\naQ").unwrap(), b"\n\x02hi");
+}
+
+#[test]
+fn raw_fields_and_heuristics() {
+    // 1: "hi", 2: {3: 150}, 4: packed [1, 300], 5: fixed32 1, 6: group {7: 1}, 8: bytes ff.
+    let payload =
+        parse_hex("0a026869 1203 189601 2203 01ac02 2d01000000 33 3801 34 4201ff").unwrap();
+    let fields = decode_raw(&payload).unwrap();
+
+    let values: Vec<(u32, &RawValue)> = fields.iter().map(|f| (f.number, &f.value)).collect();
+    assert_eq!(values[0], (1, &RawValue::String("hi".to_owned())));
+    let RawValue::Message(nested) = &fields[1].value else {
+        panic!("expected a nested message, got {:?}", fields[1].value)
+    };
+    assert_eq!(
+        (nested[0].number, &nested[0].value, nested[0].offset),
+        (3, &RawValue::Varint(150), 6)
+    );
+    assert_eq!(values[2], (4, &RawValue::PackedVarints(vec![1, 300])));
+    assert_eq!(values[3], (5, &RawValue::Fixed32(1)));
+    assert!(matches!(&fields[4].value, RawValue::Group(g) if g[0].value == RawValue::Varint(1)));
+    assert_eq!(values[5], (8, &RawValue::Bytes(vec![0xff])));
+    assert_eq!((fields[1].offset, fields[1].length), (4, 5));
+
+    assert_eq!(
+        decode_raw(&[0x0a, 0x05, 0x01]).unwrap_err().to_string(),
+        "payload is not a protobuf message: truncated field at byte 0"
+    );
+    assert_eq!(
+        decode_raw(&[0x0f]).unwrap_err().to_string(),
+        "payload is not a protobuf message: invalid wire type 7 at byte 0"
+    );
+}
@@ -0,0 +1,592 @@
+#![allow(dead_code)]
+
+//! A minimal gRPC client over HTTP/2.
+//!
+//! Messages are opaque bytes here; encoding them is up to the caller, which works from
+//! descriptors known only at runtime.
+
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use h2::client::{ResponseFuture, SendRequest};
+use h2::{RecvStream, SendStream};
+use http::{HeaderMap, HeaderValue, Method, Request};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+
+use crate::api::{Compression, MessageSize, StatusOrigin, TlsSettings};
+use crate::compression;
+use crate::json;
+use crate::tls;
+
+/// Runtime driving every connection. API functions block on it.
+pub fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("failed to start the async runtime")
+    })
+}
+
+/// gRPC status codes, by number.
+const CODES: &[&str] = &[
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+pub const CANCELLED: i32 = 1;
+pub const UNKNOWN: i32 = 2;
+pub const DEADLINE_EXCEEDED: i32 = 4;
+pub const RESOURCE_EXHAUSTED: i32 = 8;
+pub const UNIMPLEMENTED: i32 = 12;
+pub const INTERNAL: i32 = 13;
+
+/// Largest response message accepted, before and after decompression, like gRPC's default.
+pub const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Name of a status code, e.g. `NOT_FOUND`.
+pub fn code_name(code: i32) -> &'static str {
+    usize::try_from(code)
+        .ok()
+        .and_then(|c| CODES.get(c))
+        .copied()
+        .unwrap_or("UNKNOWN")
+}
+
+/// Outcome of a call, from the `grpc-status`, `grpc-message` and `grpc-status-details-bin`
+/// trailers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub code: i32,
+    pub message: String,
+    /// A serialized `google.rpc.Status` with details about an error, empty when not sent.
+    pub details: Vec<u8>,
+    pub origin: StatusOrigin,
+}
+
+impl Status {
+    pub fn from_trailers(trailers: &HeaderMap) -> Self {
+        let Some(code) = trailers.get("grpc-status") else {
+            return Status {
+                code: UNKNOWN,
+                message: "response is missing grpc-status".to_owned(),
+                details: Vec::new(),
+                origin: StatusOrigin::Server,
+            };
+        };
+        let Some(code) = code.to_str().ok().and_then(|c| c.parse().ok()) else {
+            return Status {
+                code: UNKNOWN,
+                message: format!("invalid grpc-status {:?}", code),
+                details: Vec::new(),
+                origin: StatusOrigin::Server,
+            };
+        };
+        let message = trailers
+            .get("grpc-message")
+            .map(|m| percent_decode(m.as_bytes()))
+            .unwrap_or_default();
+
+        let details = trailers
+            .get("grpc-status-details-bin")
+            .and_then(|d| d.to_str().ok())
+            .and_then(json::decode_base64)
+            .unwrap_or_default();
+
+        Status {
+            code,
+            message,
+            details,
+            origin: StatusOrigin::Server,
+        }
+    }
+
+    /// The client gave up on a call still running `timeout` after it started.
+    pub fn deadline_exceeded(timeout: Duration) -> Self {
+        Status {
+            code: DEADLINE_EXCEEDED,
+            message: format!("no response within the {}ms deadline", timeout.as_millis()),
+            details: Vec::new(),
+            origin: StatusOrigin::Deadline,
+        }
+    }
+
+    /// The client refused a response message of `len` bytes.
+    pub fn message_too_large(len: usize) -> Self {
+        Status {
+            code: RESOURCE_EXHAUSTED,
+            message: format!(
+                "response message of {} bytes is larger than the {} allowed",
+                len, MAX_MESSAGE_SIZE
+            ),
+            details: Vec::new(),
+            origin: StatusOrigin::Rejected,
+        }
+    }
+
+    /// The user cancelled a call before the server ended it.
+    pub fn cancelled() -> Self {
+        Status {
+            code: CANCELLED,
+            message: "cancelled by the user".to_owned(),
+            details: Vec::new(),
+            origin: StatusOrigin::Cancelled,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", code_name(self.code))
+        } else {
+            write!(f, "{}: {}", code_name(self.code), self.message)
+        }
+    }
+}
+
+/// `grpc-message` is percent-encoded UTF-8.
+fn percent_decode(value: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        let hex = value
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (value[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// A connection to a server, cheap to clone and share between calls.
+#[derive(Clone)]
+pub struct Channel {
+    send_request: SendRequest<Bytes>,
+    /// `:authority` of requests.
+    authority: String,
+    /// `:scheme` of requests.
+    scheme: &'static str,
+}
+
+impl Channel {
+    /// Connect to `target`, `host:port` optionally prefixed with `http://` or `https://`.
+    ///
+    /// The connection is secured with `tls`, or with default settings for an `https://`
+    /// target.
+    pub async fn connect(target: &str, tls: Option<&TlsSettings>) -> Result<Self> {
+        let (address, secure) = parse_target(target)?;
+        let default_tls = TlsSettings::default();
+        let tls = tls.or(secure.then_some(&default_tls));
+        let tcp = TcpStream::connect(&address)
+            .await
+            .with_context(|| format!("failed to connect to `{}`", address))?;
+        tcp.set_nodelay(true)?;
+
+        let Some(settings) = tls else {
+            return Ok(Channel {
+                send_request: handshake(tcp, &address).await?,
+                authority: address,
+                scheme: "http",
+            });
+        };
+        let authority = settings.authority.clone().unwrap_or(address);
+        let stream = tls::connect(tcp, host(&authority), settings).await?;
+
+        Ok(Channel {
+            send_request: handshake(stream, &authority).await?,
+            authority,
+            scheme: "https",
+        })
+    }
+
+    /// Start a call of the method at `path`, `/package.Service/Method`.
+    ///
+    /// `metadata` is sent as request headers, and the time left until `deadline` as
+    /// `grpc-timeout` so that the server gives up at the same time. Requests are compressed
+    /// with `compression`, while responses may use any encoding in
+    /// [`compression::ACCEPTED`].
+    pub async fn call(
+        &self,
+        path: &str,
+        metadata: &HeaderMap,
+        deadline: Option<Instant>,
+        compression: Compression,
+    ) -> Result<(Sender, Receiver)> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{}://{}{}", self.scheme, self.authority, path))
+            .body(())?;
+        let headers = request.headers_mut();
+        headers.extend(metadata.clone());
+        headers.insert("content-type", HeaderValue::from_static("application/grpc"));
+        headers.insert("te", HeaderValue::from_static("trailers"));
+        headers.insert(
+            "user-agent",
+            HeaderValue::from_static(concat!("grpc-debug/", env!("CARGO_PKG_VERSION"))),
+        );
+        if let Some(deadline) = deadline {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            headers.insert("grpc-timeout", timeout_header(timeout));
+        }
+        if compression != Compression::Identity {
+            headers.insert(
+                "grpc-encoding",
+                HeaderValue::from_static(compression::name(compression)),
+            );
+        }
+        headers.insert(
+            "grpc-accept-encoding",
+            HeaderValue::from_static(compression::ACCEPTED),
+        );
+
+        let mut send_request = self.send_request.clone().ready().await?;
+        let (response, stream) = send_request.send_request(request, false)?;
+
+        Ok((
+            Sender {
+                stream,
+                compression,
+            },
+            Receiver {
+                response: Some(response),
+                headers: HeaderMap::new(),
+                compression: Compression::Identity,
+                body: None,
+                buffer: BytesMut::new(),
+                trailers: None,
+                rejected: None,
+            },
+        ))
+    }
+}
+
+/// Start HTTP/2 over `io`, driving the connection in the background.
+async fn handshake<T>(io: T, authority: &str) -> Result<SendRequest<Bytes>>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (send_request, connection) = h2::client::handshake(io)
+        .await
+        .with_context(|| format!("HTTP/2 handshake with `{}` failed", authority))?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    Ok(send_request)
+}
+
+/// `host:port` of `target`, and whether its scheme asks for TLS.
+fn parse_target(target: &str) -> Result<(String, bool)> {
+    let (authority, secure) = match target.split_once("://") {
+        None => (target, false),
+        Some(("http", rest)) => (rest, false),
+        Some(("https", rest)) => (rest, true),
+        Some((scheme, _)) => bail!("unsupported scheme `{}` in `{}`", scheme, target),
+    };
+    let authority = authority.trim_end_matches('/');
+    if authority.is_empty() || !authority.contains(':') {
+        bail!("target `{}` must be of the form host:port", target);
+    }
+
+    Ok((authority.to_owned(), secure))
+}
+
+/// `grpc-timeout` value for `timeout`, rounded up to the finest unit that keeps it within the
+/// 8 digits allowed.
+fn timeout_header(timeout: Duration) -> HeaderValue {
+    const UNITS: &[(u128, &str)] = &[
+        (1, "n"),
+        (1_000, "u"),
+        (1_000_000, "m"),
+        (1_000_000_000, "S"),
+        (60_000_000_000, "M"),
+        (3_600_000_000_000, "H"),
+    ];
+    let nanos = timeout.as_nanos();
+    let (value, unit) = UNITS
+        .iter()
+        .map(|&(unit_nanos, unit)| (nanos.div_ceil(unit_nanos), unit))
+        .find(|&(value, _)| value < 100_000_000)
+        .unwrap_or((99_999_999, "H"));
+
+    HeaderValue::from_str(&format!("{}{}", value, unit)).unwrap()
+}
+
+/// Host of `authority`, without its port or the brackets of an IPv6 address.
+fn host(authority: &str) -> &str {
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() && !host.contains(':') => host,
+        Some((host, port)) if port.parse::<u16>().is_ok() && host.ends_with(']') => host,
+        _ => authority,
+    };
+
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Request half of a call.
+pub struct Sender {
+    stream: SendStream<Bytes>,
+    compression: Compression,
+}
+
+impl Sender {
+    pub fn send(&mut self, message: &[u8]) -> Result<MessageSize> {
+        let compressed = self.compression != Compression::Identity;
+        let payload = if compressed {
+            compression::compress(self.compression, message)?
+        } else {
+            message.to_vec()
+        };
+        let mut frame = BytesMut::with_capacity(5 + payload.len());
+        frame.put_u8(compressed.into());
+        frame.put_u32(payload.len() as u32);
+        frame.put_slice(&payload);
+        self.stream.send_data(frame.freeze(), false)?;
+
+        Ok(MessageSize {
+            compressed: payload.len() as u32,
+            uncompressed: message.len() as u32,
+        })
+    }
+
+    /// Half-close the call: no more messages will be sent.
+    pub fn close(&mut self) -> Result<()> {
+        self.stream.send_data(Bytes::new(), true)?;
+
+        Ok(())
+    }
+}
+
+/// A response message, decompressed.
+pub struct Message {
+    pub bytes: Bytes,
+    pub size: MessageSize,
+}
+
+/// Response half of a call.
+pub struct Receiver {
+    response: Option<ResponseFuture>,
+    headers: HeaderMap,
+    /// Encoding of compressed messages, from `grpc-encoding`.
+    compression: Compression,
+    body: Option<RecvStream>,
+    buffer: BytesMut,
+    trailers: Option<HeaderMap>,
+    /// Status the client ended the call with, instead of the server's.
+    rejected: Option<Status>,
+}
+
+impl Receiver {
+    /// Wait for the response headers.
+    pub async fn headers(&mut self) -> Result<&HeaderMap> {
+        if let Some(response) = self.response.take() {
+            let (parts, body) = response.await?.into_parts();
+            if parts.status != http::StatusCode::OK {
+                bail!("server responded with HTTP status {}", parts.status);
+            }
+            self.headers = parts.headers;
+            if let Some(encoding) = self.headers.get("grpc-encoding") {
+                let name = String::from_utf8_lossy(encoding.as_bytes());
+                self.compression = compression::from_name(&name).ok_or_else(|| {
+                    anyhow!("server compressed responses with unsupported `{}`", name)
+                })?;
+            }
+            if body.is_end_stream() {
+                // Trailers-only response: the status is in the headers.
+                self.trailers = Some(self.headers.clone());
+            } else {
+                self.body = Some(body);
+            }
+        }
+
+        Ok(&self.headers)
+    }
+
+    /// The next message, or `None` once the server has finished sending.
+    pub async fn message(&mut self) -> Result<Option<Message>> {
+        self.headers().await?;
+        loop {
+            match decode_frame(&mut self.buffer) {
+                Ok(Some((compressed, payload))) => {
+                    return self.decompress(compressed, payload).map(Some)
+                }
+                Ok(None) => {}
+                Err(len) => {
+                    // Dropping the body resets the call rather than receiving the rest.
+                    self.body = None;
+                    self.buffer.clear();
+                    self.rejected = Some(Status::message_too_large(len));
+                    return Ok(None);
+                }
+            }
+            let Some(body) = self.body.as_mut() else {
+                return Ok(None);
+            };
+            match body.data().await {
+                Some(data) => {
+                    let data = data?;
+                    body.flow_control().release_capacity(data.len())?;
+                    self.buffer.extend_from_slice(&data);
+                }
+                None => {
+                    if !self.buffer.is_empty() {
+                        bail!("response ended in the middle of a message");
+                    }
+                    self.trailers = Some(body.trailers().await?.unwrap_or_default());
+                    self.body = None;
+                }
+            }
+        }
+    }
+
+    /// Skip any remaining messages and return the status the call ended with.
+    pub async fn status(&mut self) -> Result<Status> {
+        while self.message().await?.is_some() {}
+        if let Some(status) = &self.rejected {
+            return Ok(status.clone());
+        }
+
+        Ok(Status::from_trailers(self.trailers.as_ref().unwrap()))
+    }
+
+    fn decompress(&self, compressed: bool, payload: Bytes) -> Result<Message> {
+        let bytes = match (compressed, self.compression) {
+            (false, _) => payload.clone(),
+            (true, Compression::Identity) => {
+                bail!("received a compressed message, but the server didn't set grpc-encoding")
+            }
+            (true, compression) => {
+                compression::decompress(compression, &payload, MAX_MESSAGE_SIZE)?.into()
+            }
+        };
+
+        Ok(Message {
+            size: MessageSize {
+                compressed: payload.len() as u32,
+                uncompressed: bytes.len() as u32,
+            },
+            bytes,
+        })
+    }
+
+    /// Trailers, once every message has been received.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+}
+
+/// Split the first length-prefixed message off `buffer`, if it is complete, along with
+/// whether it is compressed.
+///
+/// Fails with the length of a message over [`MAX_MESSAGE_SIZE`] as soon as its prefix is
+/// in, rather than buffering it.
+fn decode_frame(buffer: &mut BytesMut) -> Result<Option<(bool, Bytes)>, usize> {
+    if buffer.len() < 5 {
+        return Ok(None);
+    }
+    let compressed = buffer[0] != 0;
+    let len = u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(len);
+    }
+    if buffer.len() < 5 + len {
+        return Ok(None);
+    }
+    buffer.advance(5);
+
+    Ok(Some((compressed, buffer.split_to(len).freeze())))
+}
+
+#[test]
+fn frames_split_across_reads() {
+    let mut buffer = BytesMut::new();
+    buffer.extend_from_slice(&[0, 0, 0, 0, 3, b'a']);
+    assert_eq!(decode_frame(&mut buffer), Ok(None));
+    buffer.extend_from_slice(&[b'b', b'c', 1, 0, 0, 0, 0]);
+    assert_eq!(
+        decode_frame(&mut buffer),
+        Ok(Some((false, Bytes::from_static(b"abc"))))
+    );
+    assert_eq!(decode_frame(&mut buffer), Ok(Some((true, Bytes::new()))));
+
+    assert!(buffer.is_empty());
+
+    // Rejected from the prefix alone.
+    buffer.extend_from_slice(&[0, 0, 0x40, 0, 1]);
+    assert_eq!(decode_frame(&mut buffer), Err(MAX_MESSAGE_SIZE + 1));
+}
+
+#[test]
+fn targets_and_hosts() {
+    assert_eq!(
+        parse_target("localhost:50051").unwrap(),
+        ("localhost:50051".to_owned(), false)
+    );
+    assert_eq!(
+        parse_target("https://example.com:443/").unwrap(),
+        ("example.com:443".to_owned(), true)
+    );
+    assert!(parse_target("ftp://example.com:21").is_err());
+    assert_eq!(host("example.com:443"), "example.com");
+    assert_eq!(host("example.com"), "example.com");
+    assert_eq!(host("[::1]:50051"), "::1");
+    assert_eq!(host("::1"), "::1");
+}
+
+#[test]
+fn timeouts() {
+    assert_eq!(timeout_header(Duration::from_nanos(1500)), "1500n");
+    assert_eq!(timeout_header(Duration::from_millis(250)), "250000u");
+    assert_eq!(timeout_header(Duration::from_secs(90)), "90000000u");
+    assert_eq!(timeout_header(Duration::from_secs(200)), "200000m");
+    assert_eq!(timeout_header(Duration::from_secs(86_400 * 2)), "172800S");
+    assert_eq!(timeout_header(Duration::from_secs(u64::MAX)), "99999999H");
+}
+
+#[test]
+fn status_from_trailers() {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from_static("5"));
+    trailers.insert("grpc-message", HeaderValue::from_static("no%20such%20user"));
+    let status = Status::from_trailers(&trailers);
+    assert_eq!(status.to_string(), "NOT_FOUND: no such user");
+
+    assert_eq!(Status::from_trailers(&HeaderMap::new()).code, UNKNOWN);
+}
@@ -0,0 +1,1102 @@
+#![allow(dead_code)]
+
+//! Canonical proto3 JSON mapping for dynamic messages.
+//!
+//! Follows <https://protobuf.dev/programming-guides/json/>: fields are printed under their
+//! JSON name, 64-bit integers as strings, bytes as base64, enums by name, and the well-known
+//! types (`Timestamp`, `Duration`, `Struct`, wrappers, `Any`, ...) in their special forms.
+//! Parsing accepts either field name and every form the mapping allows.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use protobuf::reflect::{
+    FieldDescriptor, MessageDescriptor, ReflectFieldRef, ReflectValueBox, ReflectValueRef,
+    RuntimeFieldType, RuntimeType,
+};
+use protobuf::MessageDyn;
+use serde_json::{Map, Number, Value};
+
+use crate::codec;
+use crate::pool::DescriptorPool;
+
+/// A JSON value that doesn't fit the message it is mapped to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    /// JSON pointer to the offending value, empty for the document itself.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+type JsonResult<T> = Result<T, JsonError>;
+
+fn error<T>(path: &str, message: impl Into<String>) -> JsonResult<T> {
+    Err(JsonError {
+        path: path.to_owned(),
+        message: message.into(),
+    })
+}
+
+/// Append `token` to the JSON pointer `path`.
+pub fn pointer(path: &str, token: &str) -> String {
+    format!("{}/{}", path, token.replace('~', "~0").replace('/', "~1"))
+}
+
+const WRAPPERS: &[&str] = &[
+    "google.protobuf.DoubleValue",
+    "google.protobuf.FloatValue",
+    "google.protobuf.Int64Value",
+    "google.protobuf.UInt64Value",
+    "google.protobuf.Int32Value",
+    "google.protobuf.UInt32Value",
+    "google.protobuf.BoolValue",
+    "google.protobuf.StringValue",
+    "google.protobuf.BytesValue",
+];
+
+/// Well-known types whose JSON form isn't an object of their fields. Inside an `Any` they
+/// are put under a `value` key.
+pub fn has_special_form(full_name: &str) -> bool {
+    WRAPPERS.contains(&full_name)
+        || matches!(
+            full_name,
+            "google.protobuf.Timestamp"
+                | "google.protobuf.Duration"
+                | "google.protobuf.FieldMask"
+                | "google.protobuf.Struct"
+                | "google.protobuf.ListValue"
+                | "google.protobuf.Value"
+                | "google.protobuf.Any"
+        )
+}
+
+/// Base64 in either the standard or the URL-safe alphabet, with or without padding.
+const BASE64_CONFIG: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const BASE64: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD, BASE64_CONFIG);
+const BASE64_URL_SAFE: GeneralPurpose = GeneralPurpose::new(&alphabet::URL_SAFE, BASE64_CONFIG);
+
+pub fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    BASE64
+        .decode(text)
+        .or_else(|_| BASE64_URL_SAFE.decode(text))
+        .ok()
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// Parse `json` into a message of type `descriptor`.
+///
+/// `pool` resolves the types named in `Any` values.
+pub fn parse(
+    descriptor: &MessageDescriptor,
+    json: &str,
+    pool: &DescriptorPool,
+) -> JsonResult<Box<dyn MessageDyn>> {
+    let value: Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(e) => return error("", format!("invalid JSON: {}", e)),
+    };
+
+    from_value(descriptor, &value, pool)
+}
+
+pub fn from_value(
+    descriptor: &MessageDescriptor,
+    value: &Value,
+    pool: &DescriptorPool,
+) -> JsonResult<Box<dyn MessageDyn>> {
+    let mut message = descriptor.new_instance();
+    Parser { pool }.merge(&mut *message, value, "")?;
+
+    Ok(message)
+}
+
+/// Map `value` to a field value of type `t`, the way a field of a parsed message is.
+pub fn field_value(
+    t: &RuntimeType,
+    value: &Value,
+    path: &str,
+    pool: &DescriptorPool,
+) -> JsonResult<ReflectValueBox> {
+    Parser { pool }.value(t, value, path)
+}
+
+/// Map an object key to a map key of type `t`.
+pub fn map_key(
+    t: &RuntimeType,
+    key: &str,
+    path: &str,
+    pool: &DescriptorPool,
+) -> JsonResult<ReflectValueBox> {
+    Parser { pool }.map_key(t, key, path)
+}
+
+/// Print `message` as JSON, indented when `pretty`.
+pub fn print(message: &dyn MessageDyn, pool: &DescriptorPool, pretty: bool) -> JsonResult<String> {
+    let value = to_value(message, pool)?;
+    let json = if pretty {
+        serde_json::to_string_pretty(&value)
+    } else {
+        serde_json::to_string(&value)
+    };
+
+    Ok(json.unwrap())
+}
+
+pub fn to_value(message: &dyn MessageDyn, pool: &DescriptorPool) -> JsonResult<Value> {
+    Printer { pool }.message(message, "")
+}
+
+/// Look up `type_url`, `type.googleapis.com/package.Message`, in `pool` and then among the
+/// bundled well-known types.
+pub fn resolve_type_url(pool: &DescriptorPool, type_url: &str) -> Option<MessageDescriptor> {
+    let name = type_url.rsplit('/').next().unwrap_or_default();
+    pool.message_by_name(name).or_else(|| {
+        crate::bundled::pool()
+            .ok()
+            .and_then(|p| p.message_by_name(name))
+    })
+}
+
+fn field(message: &dyn MessageDyn, name: &str) -> FieldDescriptor {
+    message.descriptor_dyn().field_by_name(name).unwrap()
+}
+
+fn get<'a>(message: &'a dyn MessageDyn, name: &str) -> ReflectValueRef<'a> {
+    field(message, name).get_singular_field_or_default(message)
+}
+
+fn set(message: &mut dyn MessageDyn, name: &str, value: ReflectValueBox) {
+    field(message, name).set_singular_field(message, value);
+}
+
+/// Type of the singular message field `name`.
+fn message_type(message: &dyn MessageDyn, name: &str) -> MessageDescriptor {
+    match field(message, name).singular_runtime_type() {
+        RuntimeType::Message(m) => m,
+        t => panic!("`{}` is a {} field", name, t),
+    }
+}
+
+struct Parser<'a> {
+    pool: &'a DescriptorPool,
+}
+
+impl<'a> Parser<'a> {
+    fn merge(&self, message: &mut dyn MessageDyn, value: &Value, path: &str) -> JsonResult<()> {
+        let descriptor = message.descriptor_dyn();
+        let full_name = descriptor.full_name();
+        match full_name {
+            "google.protobuf.Any" => return self.merge_any(message, value, path),
+            "google.protobuf.Timestamp" => {
+                let Some((seconds, nanos)) = expect_str(value, path).map(parse_timestamp)? else {
+                    return error(
+                        path,
+                        "expected an RFC 3339 timestamp such as \"1972-01-01T10:00:20.021Z\"",
+                    );
+                };
+                set(message, "seconds", ReflectValueBox::I64(seconds));
+                set(message, "nanos", ReflectValueBox::I32(nanos));
+                return Ok(());
+            }
+            "google.protobuf.Duration" => {
+                let Some((seconds, nanos)) = expect_str(value, path).map(parse_duration)? else {
+                    return error(path, "expected a duration in seconds such as \"1.5s\"");
+                };
+                set(message, "seconds", ReflectValueBox::I64(seconds));
+                set(message, "nanos", ReflectValueBox::I32(nanos));
+                return Ok(());
+            }
+            "google.protobuf.FieldMask" => {
+                let paths = expect_str(value, path)?;
+                let mut repeated = field(message, "paths").mut_repeated(message);
+                for p in paths.split(',').filter(|p| !p.is_empty()) {
+                    repeated.push(ReflectValueBox::String(camel_to_snake(p)));
+                }
+                return Ok(());
+            }
+            "google.protobuf.Struct" => {
+                let Value::Object(object) = value else {
+                    return error(path, "expected an object");
+                };
+                let value_type = match field(message, "fields").runtime_field_type() {
+                    RuntimeFieldType::Map(_, RuntimeType::Message(m)) => m,
+                    _ => unreachable!(),
+                };
+                let mut map = field(message, "fields").mut_map(message);
+                for (key, item) in object {
+                    let mut v = value_type.new_instance();
+                    self.merge(&mut *v, item, &pointer(path, key))?;
+                    map.insert(
+                        ReflectValueBox::String(key.clone()),
+                        ReflectValueBox::Message(v),
+                    );
+                }
+                return Ok(());
+            }
+            "google.protobuf.ListValue" => {
+                let Value::Array(items) = value else {
+                    return error(path, "expected an array");
+                };
+                let value_type = match field(message, "values").runtime_field_type() {
+                    RuntimeFieldType::Repeated(RuntimeType::Message(m)) => m,
+                    _ => unreachable!(),
+                };
+                let mut repeated = field(message, "values").mut_repeated(message);
+                for (i, item) in items.iter().enumerate() {
+                    let mut v = value_type.new_instance();
+                    self.merge(&mut *v, item, &pointer(path, &i.to_string()))?;
+                    repeated.push(ReflectValueBox::Message(v));
+                }
+                return Ok(());
+            }
+            "google.protobuf.Value" => return self.merge_value(message, value, path),
+            _ if WRAPPERS.contains(&full_name) => {
+                let value_field = field(message, "value");
+                let v = self.value(&value_field.singular_runtime_type(), value, path)?;
+                value_field.set_singular_field(message, v);
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let Value::Object(object) = value else {
+            return error(path, format!("expected an object for {}", full_name));
+        };
+        let mut oneofs: HashMap<String, &str> = HashMap::new();
+        for (key, item) in object {
+            let item_path = pointer(path, key);
+            let Some(field) = descriptor
+                .fields()
+                .find(|f| f.json_name() == key || f.name() == key)
+            else {
+                return error(
+                    &item_path,
+                    format!("unknown field `{}` in {}", key, full_name),
+                );
+            };
+            if item.is_null() && !accepts_null(&field) {
+                continue;
+            }
+            if let Some(oneof) = field.containing_oneof() {
+                if let Some(other) = oneofs.insert(oneof.name().to_owned(), key) {
+                    return error(
+                        &item_path,
+                        format!(
+                            "`{}` and `{}` belong to the same oneof `{}`",
+                            other,
+                            key,
+                            oneof.name()
+                        ),
+                    );
+                }
+            }
+
+            match field.runtime_field_type() {
+                RuntimeFieldType::Singular(t) => {
+                    let v = self.value(&t, item, &item_path)?;
+                    field.set_singular_field(message, v);
+                }
+                RuntimeFieldType::Repeated(t) => {
+                    let Value::Array(items) = item else {
+                        return error(&item_path, "expected an array");
+                    };
+                    let values = items
+                        .iter()
+                        .enumerate()
+                        .map(|(i, v)| self.value(&t, v, &pointer(&item_path, &i.to_string())))
+                        .collect::<JsonResult<Vec<_>>>()?;
+                    let mut repeated = field.mut_repeated(message);
+                    for v in values {
+                        repeated.push(v);
+                    }
+                }
+                RuntimeFieldType::Map(k, t) => {
+                    let Value::Object(entries) = item else {
+                        return error(&item_path, "expected an object");
+                    };
+                    let mut pairs = Vec::new();
+                    for (key, v) in entries {
+                        let entry_path = pointer(&item_path, key);
+                        pairs.push((
+                            self.map_key(&k, key, &entry_path)?,
+                            self.value(&t, v, &entry_path)?,
+                        ));
+                    }
+                    let mut map = field.mut_map(message);
+                    for (key, v) in pairs {
+                        map.insert(key, v);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn value(&self, t: &RuntimeType, value: &Value, path: &str) -> JsonResult<ReflectValueBox> {
+        Ok(match t {
+            RuntimeType::I32 => ReflectValueBox::I32(integer(value, path, "int32")?),
+            RuntimeType::I64 => ReflectValueBox::I64(integer(value, path, "int64")?),
+            RuntimeType::U32 => ReflectValueBox::U32(integer(value, path, "uint32")?),
+            RuntimeType::U64 => ReflectValueBox::U64(integer(value, path, "uint64")?),
+            RuntimeType::F32 => {
+                let v = float(value, path)?;
+                if v.is_finite() && v.abs() > f32::MAX as f64 {
+                    return error(path, format!("{} is out of range for float", v));
+                }
+                ReflectValueBox::F32(v as f32)
+            }
+            RuntimeType::F64 => ReflectValueBox::F64(float(value, path)?),
+            RuntimeType::Bool => match value {
+                Value::Bool(b) => ReflectValueBox::Bool(*b),
+                _ => return error(path, "expected true or false"),
+            },
+            RuntimeType::String => ReflectValueBox::String(expect_str(value, path)?.to_owned()),
+            RuntimeType::VecU8 => match decode_base64(expect_str(value, path)?) {
+                Some(bytes) => ReflectValueBox::Bytes(bytes),
+                None => return error(path, "expected base64-encoded bytes"),
+            },
+            RuntimeType::Enum(e) => match value {
+                Value::Null if e.full_name() == "google.protobuf.NullValue" => {
+                    ReflectValueBox::Enum(e.clone(), 0)
+                }
+                Value::String(name) => match e.value_by_name(name) {
+                    Some(v) => ReflectValueBox::Enum(e.clone(), v.value()),
+                    None => {
+                        return error(
+                            path,
+                            format!("`{}` is not a value of enum {}", name, e.full_name()),
+                        )
+                    }
+                },
+                Value::Number(_) => ReflectValueBox::Enum(e.clone(), integer(value, path, "enum")?),
+                _ => {
+                    return error(
+                        path,
+                        format!("expected a value name of enum {}", e.full_name()),
+                    )
+                }
+            },
+            RuntimeType::Message(m) => {
+                if value.is_null() && m.full_name() != "google.protobuf.Value" {
+                    return error(path, "null is not allowed here");
+                }
+                let mut message = m.new_instance();
+                self.merge(&mut *message, value, path)?;
+                ReflectValueBox::Message(message)
+            }
+        })
+    }
+
+    fn map_key(&self, t: &RuntimeType, key: &str, path: &str) -> JsonResult<ReflectValueBox> {
+        match t {
+            RuntimeType::Bool => match key {
+                "true" => Ok(ReflectValueBox::Bool(true)),
+                "false" => Ok(ReflectValueBox::Bool(false)),
+                _ => error(path, "map key must be \"true\" or \"false\""),
+            },
+            RuntimeType::String => Ok(ReflectValueBox::String(key.to_owned())),
+            t => self.value(t, &Value::String(key.to_owned()), path),
+        }
+    }
+
+    fn merge_value(
+        &self,
+        message: &mut dyn MessageDyn,
+        value: &Value,
+        path: &str,
+    ) -> JsonResult<()> {
+        match value {
+            Value::Null => {
+                let null_value = match field(message, "null_value").singular_runtime_type() {
+                    RuntimeType::Enum(e) => e,
+                    _ => unreachable!(),
+                };
+                set(message, "null_value", ReflectValueBox::Enum(null_value, 0));
+            }
+            Value::Bool(b) => set(message, "bool_value", ReflectValueBox::Bool(*b)),
+            Value::Number(n) => set(
+                message,
+                "number_value",
+                ReflectValueBox::F64(n.as_f64().unwrap()),
+            ),
+            Value::String(s) => set(message, "string_value", ReflectValueBox::String(s.clone())),
+            Value::Array(_) => {
+                let mut list = message_type(message, "list_value").new_instance();
+                self.merge(&mut *list, value, path)?;
+                set(message, "list_value", ReflectValueBox::Message(list));
+            }
+            Value::Object(_) => {
+                let mut object = message_type(message, "struct_value").new_instance();
+                self.merge(&mut *object, value, path)?;
+                set(message, "struct_value", ReflectValueBox::Message(object));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn merge_any(&self, message: &mut dyn MessageDyn, value: &Value, path: &str) -> JsonResult<()> {
+        let Value::Object(object) = value else {
+            return error(path, "expected an object with an `@type`");
+        };
+        let Some(type_url) = object.get("@type") else {
+            return error(path, "missing `@type`");
+        };
+        let type_url = expect_str(type_url, &pointer(path, "@type"))?;
+        let Some(descriptor) = resolve_type_url(self.pool, type_url) else {
+            return error(
+                &pointer(path, "@type"),
+                format!("unknown type `{}`", type_url),
+            );
+        };
+
+        let mut packed = descriptor.new_instance();
+        if has_special_form(descriptor.full_name()) {
+            let Some(inner) = object.get("value") else {
+                return error(
+                    path,
+                    format!("missing `value` for {}", descriptor.full_name()),
+                );
+            };
+            self.merge(&mut *packed, inner, &pointer(path, "value"))?;
+        } else {
+            let mut fields = object.clone();
+            fields.remove("@type");
+            self.merge(&mut *packed, &Value::Object(fields), path)?;
+        }
+        let bytes = match codec::encode(&*packed) {
+            Ok(bytes) => bytes,
+            Err(e) => return error(path, e.to_string()),
+        };
+        set(
+            message,
+            "type_url",
+            ReflectValueBox::String(type_url.to_owned()),
+        );
+        set(message, "value", ReflectValueBox::Bytes(bytes));
+
+        Ok(())
+    }
+}
+
+/// Whether `null` sets `field` rather than leaving it unset.
+pub fn accepts_null(field: &FieldDescriptor) -> bool {
+    match field.runtime_field_type() {
+        RuntimeFieldType::Singular(RuntimeType::Message(m)) => {
+            m.full_name() == "google.protobuf.Value"
+        }
+        RuntimeFieldType::Singular(RuntimeType::Enum(e)) => {
+            e.full_name() == "google.protobuf.NullValue"
+        }
+        _ => false,
+    }
+}
+
+pub fn expect_str<'v>(value: &'v Value, path: &str) -> JsonResult<&'v str> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => error(path, "expected a string"),
+    }
+}
+
+/// An integer given as a number or a string, possibly in exponent notation.
+fn integer<T: TryFrom<i128>>(value: &Value, path: &str, type_name: &str) -> JsonResult<T> {
+    let parsed: Option<i128> = match value {
+        Value::Number(n) => n
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from))
+            .or_else(|| n.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i128)),
+        Value::String(s) => s.parse::<i128>().ok().or_else(|| {
+            s.parse::<f64>()
+                .ok()
+                .filter(|f| f.is_finite() && f.fract() == 0.0)
+                .map(|f| f as i128)
+        }),
+        _ => return error(path, format!("expected an integer ({})", type_name)),
+    };
+    let Some(parsed) = parsed else {
+        return error(path, format!("{} is not an integer", value));
+    };
+
+    match T::try_from(parsed) {
+        Ok(v) => Ok(v),
+        Err(_) => error(
+            path,
+            format!("{} is out of range for {}", parsed, type_name),
+        ),
+    }
+}
+
+fn float(value: &Value, path: &str) -> JsonResult<f64> {
+    match value {
+        Value::Number(n) => Ok(n.as_f64().unwrap()),
+        Value::String(s) => match s.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            s => match s.parse::<f64>() {
+                Ok(f) if f.is_finite() => Ok(f),
+                _ => error(path, format!("`{}` is not a number", s)),
+            },
+        },
+        _ => error(path, "expected a number"),
+    }
+}
+
+struct Printer<'a> {
+    pool: &'a DescriptorPool,
+}
+
+impl<'a> Printer<'a> {
+    fn message(&self, message: &dyn MessageDyn, path: &str) -> JsonResult<Value> {
+        let descriptor = message.descriptor_dyn();
+        let full_name = descriptor.full_name();
+        match full_name {
+            "google.protobuf.Any" => return self.any(message, path),
+            "google.protobuf.Timestamp" => {
+                let seconds = get(message, "seconds").to_i64().unwrap();
+                let nanos = get(message, "nanos").to_i32().unwrap();
+                return match format_timestamp(seconds, nanos) {
+                    Some(s) => Ok(Value::String(s)),
+                    None => error(
+                        path,
+                        format!("timestamp {}s {}ns is out of range", seconds, nanos),
+                    ),
+                };
+            }
+            "google.protobuf.Duration" => {
+                let seconds = get(message, "seconds").to_i64().unwrap();
+                let nanos = get(message, "nanos").to_i32().unwrap();
+                return match format_duration(seconds, nanos) {
+                    Some(s) => Ok(Value::String(s)),
+                    None => error(
+                        path,
+                        format!("duration {}s {}ns is out of range", seconds, nanos),
+                    ),
+                };
+            }
+            "google.protobuf.FieldMask" => {
+                let paths = field(message, "paths");
+                let paths = paths.get_repeated(message);
+                let paths: Vec<String> = (&paths)
+                    .into_iter()
+                    .map(|p| snake_to_camel(p.to_str().unwrap()))
+                    .collect();
+                return Ok(Value::String(paths.join(",")));
+            }
+            "google.protobuf.Struct" => {
+                let fields = field(message, "fields");
+                let mut object = Map::new();
+                for (key, value) in sorted_entries(&fields.get_map(message)) {
+                    let key = key.to_str().unwrap();
+                    object.insert(key.to_owned(), self.value(&value, &pointer(path, key))?);
+                }
+                return Ok(Value::Object(object));
+            }
+            "google.protobuf.ListValue" => {
+                let values = field(message, "values");
+                let values = values.get_repeated(message);
+                return (&values)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, v)| self.value(&v, &pointer(path, &i.to_string())))
+                    .collect::<JsonResult<Vec<_>>>()
+                    .map(Value::Array);
+            }
+            "google.protobuf.Value" => {
+                for name in [
+                    "null_value",
+                    "number_value",
+                    "string_value",
+                    "bool_value",
+                    "struct_value",
+                    "list_value",
+                ] {
+                    if let Some(value) = field(message, name).get_singular(message) {
+                        return self.value(&value, path);
+                    }
+                }
+                return Ok(Value::Null);
+            }
+            _ if WRAPPERS.contains(&full_name) => return self.value(&get(message, "value"), path),
+            _ => {}
+        }
+
+        let mut object = Map::new();
+        for field in descriptor.fields() {
+            let name = field.json_name().to_owned();
+            let field_path = pointer(path, &name);
+            match field.get_reflect(message) {
+                ReflectFieldRef::Optional(value) => {
+                    if let Some(value) = value.value() {
+                        if codec::has_presence(&field) || !codec::is_default(&value) {
+                            object.insert(name, self.value(&value, &field_path)?);
+                        }
+                    }
+                }
+                ReflectFieldRef::Repeated(values) => {
+                    if !values.is_empty() {
+                        let values = (&values)
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, v)| self.value(&v, &pointer(&field_path, &i.to_string())))
+                            .collect::<JsonResult<Vec<_>>>()?;
+                        object.insert(name, Value::Array(values));
+                    }
+                }
+                ReflectFieldRef::Map(map) => {
+                    if !map.is_empty() {
+                        let mut entries = Map::new();
+                        for (key, value) in sorted_entries(&map) {
+                            let key = map_key_string(&key);
+                            let value = self.value(&value, &pointer(&field_path, &key))?;
+                            entries.insert(key, value);
+                        }
+                        object.insert(name, Value::Object(entries));
+                    }
+                }
+            }
+        }
+
+        Ok(Value::Object(object))
+    }
+
+    fn value(&self, value: &ReflectValueRef, path: &str) -> JsonResult<Value> {
+        Ok(match value {
+            ReflectValueRef::I32(v) => Value::from(*v),
+            ReflectValueRef::U32(v) => Value::from(*v),
+            ReflectValueRef::I64(v) => Value::String(v.to_string()),
+            ReflectValueRef::U64(v) => Value::String(v.to_string()),
+            // Through its shortest decimal form, so that 0.1f prints as 0.1.
+            ReflectValueRef::F32(v) => float_value(v.to_string().parse().unwrap()),
+            ReflectValueRef::F64(v) => float_value(*v),
+            ReflectValueRef::Bool(v) => Value::Bool(*v),
+            ReflectValueRef::String(v) => Value::String(v.to_string()),
+            ReflectValueRef::Bytes(v) => Value::String(encode_base64(v)),
+            ReflectValueRef::Enum(e, n) => {
+                if e.full_name() == "google.protobuf.NullValue" {
+                    Value::Null
+                } else {
+                    match e.value_by_number(*n) {
+                        Some(v) => Value::String(v.name().to_owned()),
+                        None => Value::from(*n),
+                    }
+                }
+            }
+            ReflectValueRef::Message(m) => self.message(&**m, path)?,
+        })
+    }
+
+    fn any(&self, message: &dyn MessageDyn, path: &str) -> JsonResult<Value> {
+        let type_url = get(message, "type_url").to_str().unwrap().to_owned();
+        let bytes = get(message, "value").to_bytes().unwrap().to_vec();
+        if type_url.is_empty() && bytes.is_empty() {
+            return Ok(Value::Object(Map::new()));
+        }
+        let Some(descriptor) = resolve_type_url(self.pool, &type_url) else {
+            return error(path, format!("unknown type `{}` in Any", type_url));
+        };
+        let packed = match descriptor.parse_from_bytes(&bytes) {
+            Ok(packed) => packed,
+            Err(e) => {
+                return error(
+                    path,
+                    format!("invalid {} in Any: {}", descriptor.full_name(), e),
+                )
+            }
+        };
+
+        let mut object = Map::new();
+        object.insert("@type".to_owned(), Value::String(type_url));
+        if has_special_form(descriptor.full_name()) {
+            object.insert(
+                "value".to_owned(),
+                self.message(&*packed, &pointer(path, "value"))?,
+            );
+        } else if let Value::Object(fields) = self.message(&*packed, path)? {
+            object.extend(fields);
+        }
+
+        Ok(Value::Object(object))
+    }
+}
+
+fn float_value(v: f64) -> Value {
+    match Number::from_f64(v) {
+        Some(n) => Value::Number(n),
+        None if v.is_nan() => Value::String("NaN".to_owned()),
+        None if v > 0.0 => Value::String("Infinity".to_owned()),
+        None => Value::String("-Infinity".to_owned()),
+    }
+}
+
+/// A map key as it is printed in JSON.
+pub fn map_key_string(key: &ReflectValueRef) -> String {
+    match key {
+        ReflectValueRef::String(s) => s.to_string(),
+        ReflectValueRef::Bool(b) => b.to_string(),
+        ReflectValueRef::I32(v) => v.to_string(),
+        ReflectValueRef::I64(v) => v.to_string(),
+        ReflectValueRef::U32(v) => v.to_string(),
+        ReflectValueRef::U64(v) => v.to_string(),
+        key => panic!("invalid map key {:?}", key),
+    }
+}
+
+/// Map entries ordered by key, so that output is stable.
+fn sorted_entries<'a>(
+    map: &'a protobuf::reflect::ReflectMapRef<'a>,
+) -> Vec<(ReflectValueRef<'a>, ReflectValueRef<'a>)> {
+    let mut entries: Vec<_> = map.into_iter().collect();
+    entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+    entries
+}
+
+fn compare_keys(a: &ReflectValueRef, b: &ReflectValueRef) -> Ordering {
+    match (a, b) {
+        (ReflectValueRef::String(a), ReflectValueRef::String(b)) => a.cmp(b),
+        (ReflectValueRef::Bool(a), ReflectValueRef::Bool(b)) => a.cmp(b),
+        (ReflectValueRef::I32(a), ReflectValueRef::I32(b)) => a.cmp(b),
+        (ReflectValueRef::I64(a), ReflectValueRef::I64(b)) => a.cmp(b),
+        (ReflectValueRef::U32(a), ReflectValueRef::U32(b)) => a.cmp(b),
+        (ReflectValueRef::U64(a), ReflectValueRef::U64(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+fn snake_to_camel(path: &str) -> String {
+    let mut camel = String::new();
+    let mut upper = false;
+    for c in path.chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                camel.extend(c.to_uppercase());
+                upper = false;
+            }
+            c => camel.push(c),
+        }
+    }
+
+    camel
+}
+
+fn camel_to_snake(path: &str) -> String {
+    let mut snake = String::new();
+    for c in path.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+
+    snake
+}
+
+/// `0001-01-01T00:00:00Z` and `9999-12-31T23:59:59Z`.
+const MIN_TIMESTAMP: i64 = -62_135_596_800;
+const MAX_TIMESTAMP: i64 = 253_402_300_799;
+const MAX_DURATION: i64 = 315_576_000_000;
+
+/// Days since 1970-01-01 to a proleptic Gregorian `(year, month, day)`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// Fractional seconds with 0, 3, 6 or 9 digits.
+fn format_nanos(nanos: i32) -> String {
+    if nanos == 0 {
+        String::new()
+    } else if nanos % 1_000_000 == 0 {
+        format!(".{:03}", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!(".{:06}", nanos / 1_000)
+    } else {
+        format!(".{:09}", nanos)
+    }
+}
+
+pub fn format_timestamp(seconds: i64, nanos: i32) -> Option<String> {
+    if !(MIN_TIMESTAMP..=MAX_TIMESTAMP).contains(&seconds) || !(0..1_000_000_000).contains(&nanos) {
+        return None;
+    }
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let time = seconds.rem_euclid(86_400);
+
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        format_nanos(nanos)
+    ))
+}
+
+/// Parse digits after a decimal point into nanoseconds.
+fn parse_nanos(fraction: &str) -> Option<i32> {
+    if fraction.is_empty() || fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let padded = format!("{:0<9}", fraction);
+
+    padded.parse().ok()
+}
+
+pub fn parse_timestamp(text: &str) -> Option<(i64, i32)> {
+    let number = |s: &str| -> Option<i64> {
+        if s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    };
+    let bytes = text.as_bytes();
+    // The fixed-width part is sliced by byte, so it must be ASCII.
+    if bytes.len() < 20
+        || !bytes[..19].is_ascii()
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let (year, month, day) = (
+        number(&text[0..4])?,
+        number(&text[5..7])?,
+        number(&text[8..10])?,
+    );
+    let (hour, minute, second) = (
+        number(&text[11..13])?,
+        number(&text[14..16])?,
+        number(&text[17..19])?,
+    );
+
+    let rest = &text[19..];
+    let (nanos, zone) = match rest.strip_prefix('.') {
+        Some(fraction) => {
+            let end = fraction
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(fraction.len());
+            (parse_nanos(&fraction[..end])?, &fraction[end..])
+        }
+        None => (0, rest),
+    };
+    let offset = match zone {
+        "Z" | "z" => 0,
+        zone if zone.len() == 6 && zone.is_ascii() && zone.as_bytes()[3] == b':' => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            sign * (number(&zone[1..3])? * 3600 + number(&zone[4..6])? * 60)
+        }
+        _ => return None,
+    };
+
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if day < 1 || day > days_in_month || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset;
+
+    (MIN_TIMESTAMP..=MAX_TIMESTAMP)
+        .contains(&seconds)
+        .then_some((seconds, nanos))
+}
+
+pub fn format_duration(seconds: i64, nanos: i32) -> Option<String> {
+    // Checked before taking absolute values, which overflow for `MIN`.
+    if seconds.unsigned_abs() > MAX_DURATION as u64
+        || nanos.unsigned_abs() >= 1_000_000_000
+        || (seconds > 0 && nanos < 0)
+        || (seconds < 0 && nanos > 0)
+    {
+        return None;
+    }
+    let sign = if seconds < 0 || nanos < 0 { "-" } else { "" };
+
+    Some(format!(
+        "{}{}{}s",
+        sign,
+        seconds.abs(),
+        format_nanos(nanos.abs())
+    ))
+}
+
+pub fn parse_duration(text: &str) -> Option<(i64, i32)> {
+    let text = text.strip_suffix('s')?;
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let seconds: i64 = whole.parse().ok()?;
+    let nanos = if fraction.is_empty() && !text.ends_with('.') {
+        0
+    } else {
+        parse_nanos(fraction)?
+    };
+    if seconds > MAX_DURATION {
+        return None;
+    }
+
+    Some(if negative {
+        (-seconds, -nanos)
+    } else {
+        (seconds, nanos)
+    })
+}
+
+#[test]
+fn well_known_type_strings() {
+    assert_eq!(
+        format_timestamp(0, 0).as_deref(),
+        Some("1970-01-01T00:00:00Z")
+    );
+    assert_eq!(
+        format_timestamp(68_502_020, 21_000_000).as_deref(),
+        Some("1972-03-03T20:20:20.021Z")
+    );
+    assert_eq!(
+        format_timestamp(MIN_TIMESTAMP, 0).as_deref(),
+        Some("0001-01-01T00:00:00Z")
+    );
+    assert_eq!(
+        parse_timestamp("1972-03-03T21:20:20.021+01:00"),
+        Some((68_502_020, 21_000_000))
+    );
+    assert_eq!(
+        parse_timestamp("9999-12-31T23:59:59.999999999Z"),
+        Some((MAX_TIMESTAMP, 999_999_999))
+    );
+    assert_eq!(parse_timestamp("2023-02-29T00:00:00Z"), None);
+    assert_eq!(parse_timestamp("2023-01-01T00:00:0é"), None);
+    assert_eq!(parse_timestamp("2023-01-01T00:00:00.5é"), None);
+
+    assert_eq!(format_duration(-1, -500_000).as_deref(), Some("-1.000500s"));
+    assert_eq!(parse_duration("-1.0005s"), Some((-1, -500_000)));
+    assert_eq!(format_duration(i64::MIN, 0), None);
+    assert_eq!(format_duration(-1, i32::MIN), None);
+    assert_eq!(parse_duration("3s"), Some((3, 0)));
+    assert_eq!(parse_duration("3"), None);
+
+    assert_eq!(snake_to_camel("user.display_name"), "user.displayName");
+    assert_eq!(camel_to_snake("user.displayName"), "user.display_name");
+}
+
+#[test]
+fn proto3_json_mapping() {
+    let (pool, descriptor) = crate::test_server::echo_message();
+
+    let message = parse(
+        &descriptor,
+        r#"{
+            "text": "hi", "id": "-9007199254740993", "count": 7, "ratio": "NaN", "weight": 0.1,
+            "payload": "AQL_", "mood": "HAPPY", "numbers": [1, 2], "scores": {"b": 2, "a": "1"},
+            "code": 0, "maybe": 0, "child": {"mood": 2, "labels": {"10": "x", "9": "y"}},
+            "at": "1972-01-01T10:00:20.021+02:00", "elapsed": "-1.5s",
+            "extra": {"k": [null, true, 1.5, "s", {}]}, "wrapped": 0,
+            "detail": {"@type": "type.googleapis.com/google.protobuf.Duration", "value": "3s"},
+            "mask": "user.displayName,id", "value": null, "flag": false
+        }"#,
+        &pool,
+    )
+    .unwrap();
+    let expected = concat!(
+        r#"{"text":"hi","id":"-9007199254740993","count":7,"ratio":"NaN","weight":0.1,"#,
+        r#""payload":"AQL/","mood":"HAPPY","numbers":[1,2],"scores":{"a":"1","b":"2"},"#,
+        r#""code":0,"maybe":0,"child":{"mood":"GRUMPY","labels":{"9":"y","10":"x"}},"#,
+        r#""at":"1972-01-01T08:00:20.021Z","elapsed":"-1.500s","#,
+        r#""extra":{"k":[null,true,1.5,"s",{}]},"wrapped":0,"#,
+        r#""detail":{"@type":"type.googleapis.com/google.protobuf.Duration","value":"3s"},"#,
+        r#""mask":"user.displayName,id","value":null}"#
+    );
+    assert_eq!(print(&*message, &pool, false).unwrap(), expected);
+
+    // Zero values with presence survive the binary encoding.
+    let bytes = codec::encode(&*message).unwrap();
+    let decoded = descriptor.parse_from_bytes(&bytes).unwrap();
+    assert_eq!(print(&*decoded, &pool, false).unwrap(), expected);
+
+    let error = |json: &str| parse(&descriptor, json, &pool).unwrap_err().to_string();
+    assert_eq!(
+        error(r#"{"child": {"text": 5}}"#),
+        "/child/text: expected a string"
+    );
+    assert_eq!(
+        error(r#"{"name": "a", "code": 1}"#),
+        "/code: `name` and `code` belong to the same oneof `choice`"
+    );
+    assert_eq!(
+        error(r#"{"nope": 1}"#),
+        "/nope: unknown field `nope` in echo.v1.EchoMessage"
+    );
+    assert_eq!(
+        error(r#"{"count": -1}"#),
+        "/count: -1 is out of range for uint32"
+    );
+    assert_eq!(
+        error(r#"{"mood": "SAD"}"#),
+        "/mood: `SAD` is not a value of enum echo.v1.EchoMessage.Mood"
+    );
+}
@@ -1 +1,21 @@
 mod api;
+mod bundled;
+mod call;
+mod cancel;
+mod codec;
+mod compression;
+mod decode;
+mod grpc;
+mod json;
+mod metadata;
+mod pool;
+mod reflection;
+mod schema;
+mod source_info;
+mod template;
+#[cfg(test)]
+mod test_server;
+mod text_format;
+mod tls;
+mod validate;
+mod workspace;
@@ -0,0 +1,165 @@
+#![allow(dead_code)]
+
+//! Call metadata, as sent and received in HTTP/2 headers.
+//!
+//! Keys are lowercase ASCII and values printable ASCII, except for keys ending in `-bin`,
+//! whose values are arbitrary bytes sent as base64.
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::api::{MetadataEntry, MetadataValue};
+use crate::json;
+
+/// Headers the client or HTTP/2 itself sets.
+const RESERVED: &[&str] = &[
+    "content-type",
+    "te",
+    "host",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+pub fn is_binary(key: &str) -> bool {
+    key.ends_with("-bin")
+}
+
+/// The header `entry` is sent as, or why it can't be sent.
+pub fn header(entry: &MetadataEntry) -> Result<(HeaderName, HeaderValue), String> {
+    let key = entry.key.to_ascii_lowercase();
+    if key.is_empty() {
+        return Err("metadata key is empty".to_owned());
+    }
+    if let Some(c) = key
+        .chars()
+        .find(|c| !matches!(c, '0'..='9' | 'a'..='z' | '_' | '-' | '.'))
+    {
+        return Err(format!(
+            "`{}` is not allowed in metadata key `{}`",
+            c.escape_debug(),
+            entry.key
+        ));
+    }
+    if key.starts_with("grpc-") {
+        return Err(format!(
+            "`{}` is reserved: keys starting with `grpc-` are for gRPC itself",
+            entry.key
+        ));
+    }
+    if RESERVED.contains(&key.as_str()) {
+        return Err(format!(
+            "`{}` is set by the connection and can't be sent as metadata",
+            entry.key
+        ));
+    }
+
+    let value = match (&entry.value, is_binary(&key)) {
+        (MetadataValue::Binary(bytes), true) => STANDARD_NO_PAD.encode(bytes),
+        (MetadataValue::Text(text), false) => {
+            if let Some(c) = text.chars().find(|c| !(' '..='~').contains(c)) {
+                return Err(format!(
+                    "`{}` is not allowed in the value of `{}`: text values are printable ASCII, \
+                     binary ones need a key ending in `-bin`",
+                    c.escape_debug(),
+                    entry.key
+                ));
+            }
+            text.clone()
+        }
+        (MetadataValue::Text(_), true) => {
+            return Err(format!("`{}` takes a binary value", entry.key))
+        }
+        (MetadataValue::Binary(_), false) => {
+            return Err(format!(
+                "`{}` takes a text value: only keys ending in `-bin` take binary ones",
+                entry.key
+            ))
+        }
+    };
+
+    Ok((
+        HeaderName::from_bytes(key.as_bytes()).map_err(|e| e.to_string())?,
+        HeaderValue::from_str(&value).map_err(|e| e.to_string())?,
+    ))
+}
+
+pub fn to_header_map(entries: &[MetadataEntry]) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for entry in entries {
+        let (name, value) = header(entry).map_err(|e| anyhow!(e))?;
+        headers.append(name, value);
+    }
+
+    Ok(headers)
+}
+
+/// Headers or trailers received from a server. A `-bin` value that isn't base64 is kept as
+/// text.
+pub fn from_header_map(headers: &HeaderMap) -> Vec<MetadataEntry> {
+    headers
+        .iter()
+        .map(|(key, value)| {
+            let key = key.as_str().to_owned();
+            let text = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            let value = match is_binary(&key).then(|| json::decode_base64(text.trim())) {
+                Some(Some(bytes)) => MetadataValue::Binary(bytes),
+                _ => MetadataValue::Text(text),
+            };
+            MetadataEntry { key, value }
+        })
+        .collect()
+}
+
+#[test]
+fn keys_and_values() {
+    let entry = |key: &str, value: MetadataValue| MetadataEntry {
+        key: key.to_owned(),
+        value,
+    };
+    let text = |t: &str| MetadataValue::Text(t.to_owned());
+
+    let headers = to_header_map(&[
+        entry("Authorization", text("Bearer abc")),
+        entry("x-trace-bin", MetadataValue::Binary(vec![0, 1, 0xfe, 0xff])),
+    ])
+    .unwrap();
+    assert_eq!(headers["authorization"], "Bearer abc");
+    assert_eq!(headers["x-trace-bin"], "AAH+/w");
+    let mut received = headers.clone();
+    received.insert("other-bin", HeaderValue::from_static("not base64!"));
+    assert_eq!(
+        from_header_map(&received),
+        vec![
+            entry("authorization", text("Bearer abc")),
+            entry("x-trace-bin", MetadataValue::Binary(vec![0, 1, 0xfe, 0xff])),
+            entry("other-bin", text("not base64!")),
+        ]
+    );
+
+    let error = |key: &str, value| header(&entry(key, value)).unwrap_err();
+    assert_eq!(error("", text("")), "metadata key is empty");
+    assert_eq!(
+        error("x tenant", text("")),
+        "` ` is not allowed in metadata key `x tenant`"
+    );
+    assert_eq!(
+        error("grpc-timeout", text("1S")),
+        "`grpc-timeout` is reserved: keys starting with `grpc-` are for gRPC itself"
+    );
+    assert_eq!(
+        error("te", text("trailers")),
+        "`te` is set by the connection and can't be sent as metadata"
+    );
+    assert_eq!(
+        error("x-trace-bin", text("AAH+/w")),
+        "`x-trace-bin` takes a binary value"
+    );
+    assert!(error("x-tenant", text("café"))
+        .starts_with("`é` is not allowed in the value of `x-tenant`"));
+    assert!(header(&entry("x-tenant", MetadataValue::Binary(vec![1]))).is_err());
+}
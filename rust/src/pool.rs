@@ -0,0 +1,224 @@
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, bail, Result};
+use protobuf::descriptor::{FileDescriptorProto, FileDescriptorSet};
+use protobuf::reflect::{FileDescriptor, MessageDescriptor, ServiceDescriptor};
+use protobuf::Message;
+
+/// A set of linked file descriptors.
+///
+/// Files are kept in topological order: every file comes after all of its imports,
+/// so cross-file type references always resolve against an already linked dependency.
+#[derive(Debug, Clone, Default)]
+pub struct DescriptorPool {
+    files: Vec<FileDescriptor>,
+    index: HashMap<String, usize>,
+}
+
+impl DescriptorPool {
+    pub fn new(protos: Vec<FileDescriptorProto>) -> Result<Self> {
+        let mut pool = DescriptorPool::default();
+        pool.add_files(protos)?;
+
+        Ok(pool)
+    }
+
+    /// Decode a serialized `FileDescriptorSet`, as written by `protoc -o`, and link it.
+    ///
+    /// The set must be self-contained: every import of every file has to be in it, as
+    /// `--include_imports` ensures. All missing imports are reported at once.
+    pub fn from_descriptor_set(bytes: &[u8]) -> Result<Self> {
+        let set = FileDescriptorSet::parse_from_bytes(bytes)
+            .map_err(|e| anyhow!("invalid FileDescriptorSet: {}", e))?;
+        let names: HashSet<&str> = set.file.iter().map(|f| f.name()).collect();
+        let missing: Vec<String> = set
+            .file
+            .iter()
+            .flat_map(|f| {
+                f.dependency
+                    .iter()
+                    .filter(|d| !names.contains(d.as_str()))
+                    .map(move |d| format!("`{}` imports `{}`", f.name(), d))
+            })
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "descriptor set is missing dependencies: {}",
+                missing.join(", ")
+            );
+        }
+
+        DescriptorPool::new(set.file)
+    }
+
+    /// Link `protos` into the pool.
+    ///
+    /// Files already present in the pool (by name) are skipped, so the output of several
+    /// parser runs sharing common imports can be added one after another.
+    pub fn add_files(&mut self, protos: Vec<FileDescriptorProto>) -> Result<()> {
+        let mut pending = HashMap::new();
+        for proto in protos {
+            if !self.index.contains_key(proto.name()) {
+                pending.insert(proto.name().to_owned(), proto);
+            }
+        }
+
+        let mut names: Vec<String> = pending.keys().cloned().collect();
+        names.sort();
+
+        let mut order = Vec::with_capacity(names.len());
+        let mut visiting = HashSet::new();
+        let mut visited = HashSet::new();
+        for name in &names {
+            self.visit(name, &pending, &mut visiting, &mut visited, &mut order)?;
+        }
+
+        for name in order {
+            let proto = pending.remove(&name).unwrap();
+            let deps = proto
+                .dependency
+                .iter()
+                .map(|d| self.files[self.index[d]].clone())
+                .collect::<Vec<_>>();
+            let file = FileDescriptor::new_dynamic(proto, &deps)
+                .map_err(|e| anyhow!("failed to link `{}`: {}", name, e))?;
+            self.index.insert(name, self.files.len());
+            self.files.push(file);
+        }
+
+        Ok(())
+    }
+
+    fn visit(
+        &self,
+        name: &str,
+        pending: &HashMap<String, FileDescriptorProto>,
+        visiting: &mut HashSet<String>,
+        visited: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<()> {
+        if visited.contains(name) || self.index.contains_key(name) {
+            return Ok(());
+        }
+        if !visiting.insert(name.to_owned()) {
+            bail!("import cycle detected at `{}`", name);
+        }
+
+        let proto = &pending[name];
+        for dep in &proto.dependency {
+            if !pending.contains_key(dep) && !self.index.contains_key(dep) {
+                bail!("`{}` imports `{}`, which was not loaded", name, dep);
+            }
+            self.visit(dep, pending, visiting, visited, order)?;
+        }
+
+        visiting.remove(name);
+        visited.insert(name.to_owned());
+        order.push(name.to_owned());
+
+        Ok(())
+    }
+
+    /// All linked files, dependencies first.
+    pub fn files(&self) -> &[FileDescriptor] {
+        &self.files
+    }
+
+    pub fn file_by_name(&self, name: &str) -> Option<&FileDescriptor> {
+        self.index.get(name).map(|&i| &self.files[i])
+    }
+
+    /// Look up a message by its fully-qualified name, with or without the leading dot.
+    pub fn message_by_name(&self, full_name: &str) -> Option<MessageDescriptor> {
+        let full_name = format!(".{}", full_name.trim_start_matches('.'));
+        self.files
+            .iter()
+            .find_map(|f| f.message_by_full_name(&full_name))
+    }
+
+    pub fn service_by_name(&self, full_name: &str) -> Option<ServiceDescriptor> {
+        let full_name = full_name.trim_start_matches('.');
+        self.files.iter().find_map(|f| {
+            f.services()
+                .find(|s| qualified_name(s.proto().name(), f.package()) == full_name)
+        })
+    }
+}
+
+/// Join a package and a package-relative name the way protobuf does.
+pub fn qualified_name(name: &str, package: &str) -> String {
+    if package.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", package, name)
+    }
+}
+
+#[test]
+fn links_imports_in_topological_order() {
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .include("testdata/imports")
+        .input("testdata/imports/user.proto")
+        .parse_and_typecheck()
+        .unwrap();
+
+    let mut protos = parsed.file_descriptors;
+    protos.reverse();
+    let pool = DescriptorPool::new(protos).unwrap();
+
+    let names: Vec<_> = pool.files().iter().map(|f| f.name().to_owned()).collect();
+    assert_eq!(
+        names,
+        [
+            "google/protobuf/timestamp.proto",
+            "common/types.proto",
+            "user.proto"
+        ]
+    );
+
+    let user = pool.message_by_name(".user.User").unwrap();
+    let created = user.field_by_name("created").unwrap();
+    let audit = match created.singular_runtime_type() {
+        protobuf::reflect::RuntimeType::Message(m) => m,
+        t => panic!("unexpected type {:?}", t),
+    };
+    assert_eq!(audit.full_name(), "common.Audit");
+    assert!(pool.service_by_name("user.UserService").is_some());
+}
+
+#[test]
+fn reports_missing_import() {
+    let mut proto = FileDescriptorProto::new();
+    proto.set_name("a.proto".to_owned());
+    proto.dependency.push("b.proto".to_owned());
+
+    let err = DescriptorPool::new(vec![proto]).unwrap_err();
+    assert!(err.to_string().contains("`a.proto` imports `b.proto`"));
+}
+
+#[test]
+fn descriptor_set_must_contain_every_import() {
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .include("testdata/imports")
+        .input("testdata/imports/user.proto")
+        .parse_and_typecheck()
+        .unwrap();
+    let mut set = FileDescriptorSet::new();
+    set.file = parsed.file_descriptors;
+
+    let pool = DescriptorPool::from_descriptor_set(&set.write_to_bytes().unwrap()).unwrap();
+    assert!(pool.message_by_name("user.User").is_some());
+
+    set.file
+        .retain(|f| f.name() != "google/protobuf/timestamp.proto");
+    let err = DescriptorPool::from_descriptor_set(&set.write_to_bytes().unwrap()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "descriptor set is missing dependencies: \
+         `common/types.proto` imports `google/protobuf/timestamp.proto`"
+    );
+}
@@ -0,0 +1,286 @@
+#![allow(dead_code)]
+
+//! Client for the gRPC server reflection service.
+//!
+//! Both `grpc.reflection.v1` and the older `grpc.reflection.v1alpha` are supported; they
+//! only differ in the package name. v1 is tried first and v1alpha is used when the server
+//! doesn't implement it.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use http::HeaderMap;
+use protobuf::descriptor::FileDescriptorProto;
+use protobuf::reflect::{MessageDescriptor, ReflectValueBox};
+use protobuf::{Message, MessageDyn};
+
+use crate::api::CallOptions;
+use crate::bundled;
+use crate::grpc::{self, Channel, Receiver, Sender, Status};
+
+const PACKAGES: [&str; 2] = ["grpc.reflection.v1", "grpc.reflection.v1alpha"];
+
+pub struct ReflectionClient {
+    channel: Channel,
+    metadata: HeaderMap,
+    options: CallOptions,
+    /// When requests must be answered by, from the timeout of `options`.
+    deadline: Option<Instant>,
+    /// Package of the reflection service the server implements, once known.
+    package: Option<&'static str>,
+    /// The reflection stream, kept open for subsequent requests.
+    stream: Option<(Sender, Receiver)>,
+}
+
+impl ReflectionClient {
+    /// Connect to the server at `target` with `options`, sending `metadata` with requests.
+    ///
+    /// The timeout of `options` runs from now and covers every request.
+    pub async fn connect(target: &str, options: &CallOptions, metadata: HeaderMap) -> Result<Self> {
+        let deadline = options
+            .timeout_ms
+            .map(|ms| Instant::now() + Duration::from_millis(ms.into()));
+        let connect = Channel::connect(target, options.tls.as_ref());
+        let channel = match deadline {
+            Some(at) => tokio::time::timeout_at(at.into(), connect)
+                .await
+                .map_err(|_| deadline_exceeded(options))??,
+            None => connect.await?,
+        };
+
+        Ok(ReflectionClient {
+            channel,
+            metadata,
+            options: options.clone(),
+            deadline,
+            package: None,
+            stream: None,
+        })
+    }
+
+    /// Fully-qualified names of the services the server exposes.
+    pub async fn list_services(&mut self) -> Result<Vec<String>> {
+        // The value is ignored, but an empty string wouldn't be encoded at all.
+        let response = self.request("list_services", "*").await?;
+        let list = message_field(&*response, "list_services_response")
+            .context("unexpected response to list_services")?;
+        let services = list.descriptor_dyn().field_by_name("service").unwrap();
+        let services = services.get_repeated(&*list);
+
+        Ok((0..services.len())
+            .map(|i| {
+                let service = services.get(i).to_message().unwrap();
+                string_field(&*service, "name")
+            })
+            .collect())
+    }
+
+    /// The file declaring `symbol`, a fully-qualified service, message or enum name,
+    /// followed by everything it imports.
+    pub async fn file_containing_symbol(
+        &mut self,
+        symbol: &str,
+    ) -> Result<Vec<FileDescriptorProto>> {
+        let symbol = symbol.trim_start_matches('.');
+        let mut files = self.file_request("file_containing_symbol", symbol).await?;
+        self.add_dependencies(&mut files).await?;
+
+        Ok(files)
+    }
+
+    /// The file named `name` followed by everything it imports.
+    pub async fn file_by_filename(&mut self, name: &str) -> Result<Vec<FileDescriptorProto>> {
+        let mut files = self.file_request("file_by_filename", name).await?;
+        self.add_dependencies(&mut files).await?;
+
+        Ok(files)
+    }
+
+    /// Servers usually send a file together with its imports, but they don't have to; fetch
+    /// whatever is missing one file at a time.
+    async fn add_dependencies(&mut self, files: &mut Vec<FileDescriptorProto>) -> Result<()> {
+        let mut known: HashSet<String> = files.iter().map(|f| f.name().to_owned()).collect();
+        let mut i = 0;
+        while i < files.len() {
+            let missing: Vec<String> = files[i]
+                .dependency
+                .iter()
+                .filter(|d| !known.contains(*d))
+                .cloned()
+                .collect();
+            for dependency in missing {
+                for file in self.file_request("file_by_filename", &dependency).await? {
+                    if known.insert(file.name().to_owned()) {
+                        files.push(file);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn file_request(&mut self, field: &str, value: &str) -> Result<Vec<FileDescriptorProto>> {
+        let response = self.request(field, value).await?;
+        let files = message_field(&*response, "file_descriptor_response")
+            .with_context(|| format!("unexpected response to {} `{}`", field, value))?;
+        let protos = files
+            .descriptor_dyn()
+            .field_by_name("file_descriptor_proto")
+            .unwrap();
+        let protos = protos.get_repeated(&*files);
+
+        (0..protos.len())
+            .map(|i| {
+                FileDescriptorProto::parse_from_bytes(protos.get(i).to_bytes().unwrap())
+                    .context("server sent an invalid file descriptor")
+            })
+            .collect()
+    }
+
+    /// Send a `ServerReflectionRequest` with `field` set to `value` and wait for the answer,
+    /// failing if it doesn't come before the deadline.
+    async fn request(&mut self, field: &str, value: &str) -> Result<Box<dyn MessageDyn>> {
+        let Some(deadline) = self.deadline else {
+            return self.exchange(field, value).await;
+        };
+        match tokio::time::timeout_at(deadline.into(), self.exchange(field, value)).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.stream = None;
+                Err(deadline_exceeded(&self.options))
+            }
+        }
+    }
+
+    async fn exchange(&mut self, field: &str, value: &str) -> Result<Box<dyn MessageDyn>> {
+        let packages = match self.package {
+            Some(package) => vec![package],
+            None => PACKAGES.to_vec(),
+        };
+
+        for package in packages {
+            let descriptor = message_descriptor(package, "ServerReflectionRequest")?;
+            let mut request = descriptor.new_instance();
+            descriptor
+                .field_by_name(field)
+                .unwrap()
+                .set_singular_field(&mut *request, ReflectValueBox::String(value.to_owned()));
+            let request = request.write_to_bytes_dyn()?;
+
+            if self.stream.is_none() {
+                let path = format!("/{}.ServerReflection/ServerReflectionInfo", package);
+                self.stream = Some(
+                    self.channel
+                        .call(
+                            &path,
+                            &self.metadata,
+                            self.deadline,
+                            self.options.compression,
+                        )
+                        .await?,
+                );
+            }
+            let (sender, receiver) = self.stream.as_mut().unwrap();
+            // A server without the service may reject the call before reading the request,
+            // in which case the status says more than the failed send.
+            let sent = sender.send(&request).map(drop);
+            let Some(response) = receiver.message().await? else {
+                let status = receiver.status().await?;
+                self.stream = None;
+                if status.code == grpc::UNIMPLEMENTED && self.package.is_none() {
+                    continue;
+                }
+                bail!("reflection request failed: {}", status);
+            };
+            sent?;
+            self.package = Some(package);
+
+            let response = message_descriptor(package, "ServerReflectionResponse")?
+                .parse_from_bytes(&response.bytes)
+                .context("server sent an invalid reflection response")?;
+            if let Some(error) = message_field(&*response, "error_response") {
+                let code = error
+                    .descriptor_dyn()
+                    .field_by_name("error_code")
+                    .unwrap()
+                    .get_singular_field_or_default(&*error)
+                    .to_i32()
+                    .unwrap();
+                bail!(
+                    "reflection request {} `{}` failed: {}: {}",
+                    field,
+                    value,
+                    grpc::code_name(code),
+                    string_field(&*error, "error_message")
+                );
+            }
+
+            return Ok(response);
+        }
+
+        bail!("server doesn't implement the reflection service")
+    }
+}
+
+fn deadline_exceeded(options: &CallOptions) -> anyhow::Error {
+    let timeout = Duration::from_millis(options.timeout_ms.unwrap_or_default().into());
+    anyhow!(
+        "reflection request failed: {}",
+        Status::deadline_exceeded(timeout)
+    )
+}
+
+fn message_descriptor(package: &str, name: &str) -> Result<MessageDescriptor> {
+    let full_name = format!("{}.{}", package, name);
+    bundled::pool()?
+        .message_by_name(&full_name)
+        .with_context(|| format!("`{}` is missing from the bundled protos", full_name))
+}
+
+fn message_field(message: &dyn MessageDyn, name: &str) -> Option<Box<dyn MessageDyn>> {
+    let field = message.descriptor_dyn().field_by_name(name).unwrap();
+    field
+        .get_singular(message)
+        .and_then(|v| v.to_message())
+        .map(|m| m.clone_box())
+}
+
+fn string_field(message: &dyn MessageDyn, name: &str) -> String {
+    let field = message.descriptor_dyn().field_by_name(name).unwrap();
+    field
+        .get_singular_field_or_default(message)
+        .to_str()
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Fetch the files declaring `symbols`, fully-qualified names, and everything they import
+/// from the server at `target`, connecting like [`ReflectionClient::connect`] does. When
+/// `symbols` is empty, the files of every service the server lists are fetched.
+pub async fn fetch_files(
+    target: &str,
+    options: &CallOptions,
+    metadata: HeaderMap,
+    symbols: &[String],
+) -> Result<Vec<FileDescriptorProto>> {
+    let mut client = ReflectionClient::connect(target, options, metadata).await?;
+    let symbols = match symbols {
+        [] => client.list_services().await?,
+        symbols => symbols.to_vec(),
+    };
+
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    for symbol in symbols {
+        for file in client.file_containing_symbol(&symbol).await? {
+            if seen.insert(file.name().to_owned()) {
+                files.push(file);
+            }
+        }
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,102 @@
+#![allow(dead_code)]
+
+//! Schemas loaded through the API, kept around to encode and decode calls.
+//!
+//! Every successful load registers its pool. Lookups search the most recent pools first, so
+//! reloading an edited file takes effect right away.
+
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Result};
+use protobuf::reflect::{MessageDescriptor, MethodDescriptor};
+
+use crate::pool::DescriptorPool;
+
+static POOLS: RwLock<Vec<Arc<DescriptorPool>>> = RwLock::new(Vec::new());
+
+/// Make the types of `pool` available to calls.
+///
+/// Pools made redundant by `pool`, those whose files it all contains, are dropped.
+pub fn register(pool: DescriptorPool) -> Arc<DescriptorPool> {
+    let pool = Arc::new(pool);
+    if !pool.files().is_empty() {
+        let mut pools = POOLS.write().unwrap();
+        pools.retain(|p| {
+            p.files()
+                .iter()
+                .any(|f| pool.file_by_name(f.name()).is_none())
+        });
+        pools.push(pool.clone());
+    }
+
+    pool
+}
+
+/// The message named `full_name` and the pool declaring it.
+pub fn message_by_name(full_name: &str) -> Result<(MessageDescriptor, Arc<DescriptorPool>)> {
+    let pools = POOLS.read().unwrap();
+    for pool in pools.iter().rev() {
+        if let Some(message) = pool.message_by_name(full_name) {
+            return Ok((message, pool.clone()));
+        }
+    }
+
+    bail!(
+        "message `{}` is not declared in any loaded proto",
+        full_name.trim_start_matches('.')
+    )
+}
+
+/// Every message of every loaded proto, nested ones included but not map entries.
+pub fn messages() -> Vec<MessageDescriptor> {
+    let pools = POOLS.read().unwrap();
+    let mut seen = HashSet::new();
+    let mut messages = Vec::new();
+    let mut stack: Vec<MessageDescriptor> = pools
+        .iter()
+        .rev()
+        .flat_map(|p| p.files().iter().flat_map(|f| f.messages()))
+        .collect();
+    stack.reverse();
+    while let Some(message) = stack.pop() {
+        if message.is_map_entry() || !seen.insert(message.full_name().to_owned()) {
+            continue;
+        }
+        stack.extend(message.nested_messages());
+        messages.push(message);
+    }
+
+    messages
+}
+
+/// The request path of the method named `name`, the method and the pool declaring it.
+///
+/// `name` is either the request path, `/package.Service/Method`, or the method's
+/// fully-qualified name, `package.Service.Method`.
+pub fn method_by_name(name: &str) -> Result<(String, MethodDescriptor, Arc<DescriptorPool>)> {
+    let trimmed = name.trim_start_matches(['/', '.']);
+    let Some((service, method)) = trimmed.split_once('/').or_else(|| trimmed.rsplit_once('.'))
+    else {
+        bail!("`{}` is not a fully-qualified method name", name);
+    };
+
+    let pools = POOLS.read().unwrap();
+    for pool in pools.iter().rev() {
+        if let Some(service_descriptor) = pool.service_by_name(service) {
+            return match service_descriptor
+                .methods()
+                .find(|m| m.proto().name() == method)
+            {
+                Some(method) => Ok((
+                    format!("/{}/{}", service, method.proto().name()),
+                    method,
+                    pool.clone(),
+                )),
+                None => bail!("service `{}` has no method `{}`", service, method),
+            };
+        }
+    }
+
+    bail!("service `{}` is not declared in any loaded proto", service)
+}
@@ -0,0 +1,637 @@
+#![allow(dead_code)]
+
+//! Comments and source spans for `.proto` files.
+//!
+//! `protoc` records these in `FileDescriptorProto.source_code_info` when asked to, but the
+//! pure parser drops them, so for its output the same information is recovered here by
+//! scanning the source again. Comments are attached following `protoc`'s rules: a comment
+//! block directly above a declaration is its leading comment, one on the same line after it
+//! (or on the next line, when followed by a blank line) is its trailing comment, and other
+//! blocks in between are detached.
+
+use protobuf::descriptor::source_code_info::Location;
+use protobuf::descriptor::{DescriptorProto, FileDescriptorProto, SourceCodeInfo};
+
+// Field numbers in descriptor.proto, used to build location paths.
+const FILE_PACKAGE: i32 = 2;
+const FILE_MESSAGE_TYPE: i32 = 4;
+const FILE_ENUM_TYPE: i32 = 5;
+const FILE_SERVICE: i32 = 6;
+const FILE_SYNTAX: i32 = 12;
+const MESSAGE_FIELD: i32 = 2;
+const MESSAGE_NESTED_TYPE: i32 = 3;
+const MESSAGE_ENUM_TYPE: i32 = 4;
+const MESSAGE_ONEOF_DECL: i32 = 8;
+const ENUM_VALUE: i32 = 2;
+const SERVICE_METHOD: i32 = 2;
+
+#[derive(Debug)]
+struct Token {
+    text: String,
+    line: i32,
+    col: i32,
+    end_line: i32,
+    end_col: i32,
+}
+
+#[derive(Debug)]
+struct Comment {
+    text: String,
+    line: i32,
+    end_line: i32,
+    /// Index of the last token before the comment.
+    after: Option<usize>,
+    line_comment: bool,
+}
+
+fn tokenize(source: &str) -> (Vec<Token>, Vec<Comment>) {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut comments: Vec<Comment> = Vec::new();
+    let (mut i, mut line, mut col) = (0, 0, 0);
+
+    macro_rules! bump {
+        () => {{
+            if chars[i] == '\n' {
+                line += 1;
+                col = 0;
+            } else {
+                col += 1;
+            }
+            i += 1;
+        }};
+    }
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (start_line, start_col, start) = (line, col, i);
+        if c.is_whitespace() {
+            bump!();
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                bump!();
+            }
+            let text: String = chars[start + 2..i].iter().collect();
+            comments.push(Comment {
+                text: format!("{}\n", text.trim_end_matches('\r')),
+                line: start_line,
+                end_line: start_line,
+                after: tokens.len().checked_sub(1),
+                line_comment: true,
+            });
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            bump!();
+            bump!();
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                bump!();
+            }
+            let body: String = chars[start + 2..i.min(chars.len())].iter().collect();
+            if i < chars.len() {
+                bump!();
+                bump!();
+            }
+            comments.push(Comment {
+                text: block_comment_text(&body),
+                line: start_line,
+                end_line: line,
+                after: tokens.len().checked_sub(1),
+                line_comment: false,
+            });
+        } else {
+            if c == '"' || c == '\'' {
+                bump!();
+                while i < chars.len() && chars[i] != c && chars[i] != '\n' {
+                    if chars[i] == '\\' {
+                        bump!();
+                    }
+                    if i < chars.len() {
+                        bump!();
+                    }
+                }
+                if i < chars.len() && chars[i] == c {
+                    bump!();
+                }
+            } else if c.is_alphanumeric() || c == '_' || c == '.' {
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+                {
+                    bump!();
+                }
+            } else {
+                bump!();
+            }
+            tokens.push(Token {
+                text: chars[start..i].iter().collect(),
+                line: start_line,
+                col: start_col,
+                end_line: line,
+                end_col: col,
+            });
+        }
+    }
+
+    (tokens, comments)
+}
+
+/// Strip the `/*`, `*/` delimiters and the leading `*` of continuation lines.
+fn block_comment_text(body: &str) -> String {
+    let lines: Vec<&str> = body.lines().collect();
+    let mut text = String::new();
+    for (i, l) in lines.iter().enumerate() {
+        let l = if i == 0 {
+            *l
+        } else {
+            let trimmed = l.trim_start();
+            trimmed.strip_prefix('*').unwrap_or(trimmed)
+        };
+        text.push_str(l);
+        if i + 1 < lines.len() {
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeclKind {
+    Syntax,
+    Package,
+    Message,
+    Enum,
+    Service,
+    Oneof,
+    Field,
+    EnumValue,
+    Method,
+}
+
+#[derive(Debug)]
+struct Decl {
+    kind: DeclKind,
+    name: String,
+    /// Index of the first token.
+    start: usize,
+    /// Index of the `;` or `{` ending the declaration header.
+    header_end: usize,
+    /// Index of the last token, the closing `}` for blocks.
+    end: usize,
+    children: Vec<Decl>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    File,
+    Message,
+    Enum,
+    Service,
+    Other,
+}
+
+struct Scanner<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn text(&self, i: usize) -> &str {
+        self.tokens.get(i).map(|t| t.text.as_str()).unwrap_or("")
+    }
+
+    /// Skip a balanced `{ ... }` starting at `self.pos`.
+    fn skip_braces(&mut self) {
+        let mut depth = 0;
+        while self.pos < self.tokens.len() {
+            match self.text(self.pos) {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+            if depth == 0 {
+                return;
+            }
+        }
+    }
+
+    fn body(&mut self, context: Context) -> Vec<Decl> {
+        let mut decls = Vec::new();
+        while self.pos < self.tokens.len() && self.text(self.pos) != "}" {
+            let start = self.pos;
+            if self.text(start) == ";" {
+                self.pos += 1;
+                continue;
+            }
+
+            // Find the end of the header, skipping option values in brackets and aggregates.
+            let mut depth = 0;
+            let mut equals = None;
+            while self.pos < self.tokens.len() {
+                match self.text(self.pos) {
+                    "(" | "[" => depth += 1,
+                    ")" | "]" => depth -= 1,
+                    "=" if depth == 0 && equals.is_none() => equals = Some(self.pos),
+                    "{" if depth > 0 || self.text(start) == "option" => {
+                        self.skip_braces();
+                        continue;
+                    }
+                    ";" | "{" if depth == 0 => break,
+                    _ => {}
+                }
+                self.pos += 1;
+            }
+            let header_end = self.pos.min(self.tokens.len().saturating_sub(1));
+            let first = self.text(start).to_owned();
+            let second = self.text(start + 1).to_owned();
+
+            if self.text(header_end) == "{" {
+                self.pos += 1;
+                let group = (start..header_end).find(|&i| self.text(i) == "group");
+                let (kind, name, child_context) = match (context, first.as_str()) {
+                    (Context::File | Context::Message, "message") => {
+                        (Some(DeclKind::Message), second, Context::Message)
+                    }
+                    (Context::File | Context::Message, "enum") => {
+                        (Some(DeclKind::Enum), second, Context::Enum)
+                    }
+                    (Context::File, "service") => {
+                        (Some(DeclKind::Service), second, Context::Service)
+                    }
+                    (Context::Message, "oneof") => {
+                        (Some(DeclKind::Oneof), second, Context::Message)
+                    }
+                    (Context::Service, "rpc") => (Some(DeclKind::Method), second, Context::Other),
+                    (Context::Message, _) if group.is_some() => (
+                        Some(DeclKind::Message),
+                        self.text(group.unwrap() + 1).to_owned(),
+                        Context::Message,
+                    ),
+                    _ => (None, String::new(), Context::Other),
+                };
+                let children = self.body(child_context);
+                let end = self.pos.min(self.tokens.len().saturating_sub(1));
+                self.pos += 1;
+                if self.text(self.pos) == ";" && kind == Some(DeclKind::Method) {
+                    self.pos += 1;
+                }
+
+                if let Some(kind) = kind {
+                    if group.is_some() && kind == DeclKind::Message {
+                        decls.push(Decl {
+                            kind: DeclKind::Field,
+                            name: name.to_lowercase(),
+                            start,
+                            header_end,
+                            end,
+                            children: Vec::new(),
+                        });
+                    }
+                    decls.push(Decl {
+                        kind,
+                        name,
+                        start,
+                        header_end,
+                        end,
+                        children,
+                    });
+                }
+            } else {
+                self.pos += 1;
+                let decl = match (context, first.as_str()) {
+                    (_, "option" | "import" | "reserved" | "extensions") => None,
+                    (Context::File, "syntax" | "edition") => Some((DeclKind::Syntax, first)),
+                    (Context::File, "package") => Some((DeclKind::Package, second)),
+                    (Context::Message, _) => {
+                        equals.map(|e| (DeclKind::Field, self.text(e - 1).to_owned()))
+                    }
+                    (Context::Enum, _) => Some((DeclKind::EnumValue, first)),
+                    (Context::Service, "rpc") => Some((DeclKind::Method, second)),
+                    _ => None,
+                };
+                if let Some((kind, name)) = decl {
+                    decls.push(Decl {
+                        kind,
+                        name,
+                        start,
+                        header_end,
+                        end: header_end,
+                        children: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        decls
+    }
+}
+
+fn index_of<T>(items: &[T], name: &str, item_name: impl Fn(&T) -> &str) -> Option<i32> {
+    items
+        .iter()
+        .position(|i| item_name(i) == name)
+        .map(|i| i as i32)
+}
+
+struct Builder<'a> {
+    tokens: &'a [Token],
+    comments: &'a [Comment],
+    locations: Vec<Location>,
+}
+
+impl<'a> Builder<'a> {
+    fn add(&mut self, decl: &Decl, path: Vec<i32>) {
+        let start = &self.tokens[decl.start];
+        let end = &self.tokens[decl.end];
+        let mut location = Location::new();
+        location.path = path;
+        location.span = if start.line == end.end_line {
+            vec![start.line, start.col, end.end_col]
+        } else {
+            vec![start.line, start.col, end.end_line, end.end_col]
+        };
+        self.attach_comments(decl, &mut location);
+        self.locations.push(location);
+    }
+
+    fn attach_comments(&self, decl: &Decl, location: &mut Location) {
+        // Trailing: right after the header, unless it's directly followed by more code.
+        let after_header = self.blocks(Some(decl.header_end));
+        let header_end = &self.tokens[decl.header_end];
+        let next_line = self
+            .tokens
+            .get(decl.header_end + 1)
+            .map(|t| t.line)
+            .unwrap_or(i32::MAX);
+        if let Some(first) = after_header.first() {
+            let following = after_header.get(1).map(|b| b.0).unwrap_or(next_line);
+            if first.0 == header_end.end_line
+                || (first.0 == header_end.end_line + 1 && following > first.1 + 1)
+            {
+                location.trailing_comments = Some(first.2.clone());
+            }
+        }
+
+        // Leading and detached: between the previous token and the declaration.
+        let mut before = self.blocks(decl.start.checked_sub(1));
+        if let Some(previous) = decl.start.checked_sub(1).map(|p| &self.tokens[p]) {
+            // The first block may already be the previous declaration's trailing comment.
+            if let Some(first) = before.first() {
+                let following = before
+                    .get(1)
+                    .map(|b| b.0)
+                    .unwrap_or(self.tokens[decl.start].line);
+                if matches!(previous.text.as_str(), ";" | "{")
+                    && (first.0 == previous.end_line
+                        || (first.0 == previous.end_line + 1 && following > first.1 + 1))
+                {
+                    before.remove(0);
+                }
+            }
+        }
+        let decl_line = self.tokens[decl.start].line;
+        if let Some(last) = before.last() {
+            if last.1 + 1 == decl_line || last.1 == decl_line {
+                location.leading_comments = Some(before.pop().unwrap().2);
+            }
+        }
+        location.leading_detached_comments = before.into_iter().map(|b| b.2).collect();
+    }
+
+    /// Comment blocks after token `after`, as `(first line, last line, text)`. Consecutive
+    /// `//` lines form a single block.
+    fn blocks(&self, after: Option<usize>) -> Vec<(i32, i32, String)> {
+        let previous_line = after.map(|a| self.tokens[a].end_line);
+        let mut blocks: Vec<(i32, i32, String, bool)> = Vec::new();
+        for comment in self.comments.iter().filter(|c| c.after == after) {
+            if let Some(last) = blocks.last_mut() {
+                if last.3
+                    && comment.line_comment
+                    && comment.line == last.1 + 1
+                    && Some(last.0) != previous_line
+                {
+                    last.1 = comment.line;
+                    last.2.push_str(&comment.text);
+                    continue;
+                }
+            }
+            blocks.push((
+                comment.line,
+                comment.end_line,
+                comment.text.clone(),
+                comment.line_comment,
+            ));
+        }
+
+        blocks.into_iter().map(|(l, e, t, _)| (l, e, t)).collect()
+    }
+
+    fn file(&mut self, decls: &[Decl], file: &FileDescriptorProto) {
+        for decl in decls {
+            let path = match decl.kind {
+                DeclKind::Syntax => Some(vec![FILE_SYNTAX]),
+                DeclKind::Package => Some(vec![FILE_PACKAGE]),
+                DeclKind::Message => index_of(&file.message_type, &decl.name, |m| m.name())
+                    .map(|i| vec![FILE_MESSAGE_TYPE, i]),
+                DeclKind::Enum => index_of(&file.enum_type, &decl.name, |e| e.name())
+                    .map(|i| vec![FILE_ENUM_TYPE, i]),
+                DeclKind::Service => {
+                    index_of(&file.service, &decl.name, |s| s.name()).map(|i| vec![FILE_SERVICE, i])
+                }
+                _ => None,
+            };
+            let Some(path) = path else { continue };
+            self.add(decl, path.clone());
+
+            match decl.kind {
+                DeclKind::Message => {
+                    let message = &file.message_type[path[1] as usize];
+                    self.message(&decl.children, message, &path);
+                }
+                DeclKind::Enum => {
+                    let e = &file.enum_type[path[1] as usize];
+                    self.enum_values(
+                        &decl.children,
+                        |n| index_of(&e.value, n, |v| v.name()),
+                        &path,
+                    );
+                }
+                DeclKind::Service => {
+                    let service = &file.service[path[1] as usize];
+                    for child in &decl.children {
+                        if let Some(i) = index_of(&service.method, &child.name, |m| m.name()) {
+                            self.add(child, [path.as_slice(), &[SERVICE_METHOD, i]].concat());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn message(&mut self, decls: &[Decl], message: &DescriptorProto, path: &[i32]) {
+        for decl in decls {
+            match decl.kind {
+                DeclKind::Field => {
+                    if let Some(i) = index_of(&message.field, &decl.name, |f| f.name()) {
+                        self.add(decl, [path, &[MESSAGE_FIELD, i]].concat());
+                    }
+                }
+                DeclKind::Oneof => {
+                    if let Some(i) = index_of(&message.oneof_decl, &decl.name, |o| o.name()) {
+                        self.add(decl, [path, &[MESSAGE_ONEOF_DECL, i]].concat());
+                    }
+                    self.message(&decl.children, message, path);
+                }
+                DeclKind::Message => {
+                    if let Some(i) = index_of(&message.nested_type, &decl.name, |m| m.name()) {
+                        let nested_path = [path, &[MESSAGE_NESTED_TYPE, i]].concat();
+                        self.add(decl, nested_path.clone());
+                        self.message(
+                            &decl.children,
+                            &message.nested_type[i as usize],
+                            &nested_path,
+                        );
+                    }
+                }
+                DeclKind::Enum => {
+                    if let Some(i) = index_of(&message.enum_type, &decl.name, |e| e.name()) {
+                        let enum_path = [path, &[MESSAGE_ENUM_TYPE, i]].concat();
+                        self.add(decl, enum_path.clone());
+                        let e = &message.enum_type[i as usize];
+                        self.enum_values(
+                            &decl.children,
+                            |n| index_of(&e.value, n, |v| v.name()),
+                            &enum_path,
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn enum_values(&mut self, decls: &[Decl], index: impl Fn(&str) -> Option<i32>, path: &[i32]) {
+        for decl in decls {
+            if let Some(i) = index(&decl.name) {
+                self.add(decl, [path, &[ENUM_VALUE, i]].concat());
+            }
+        }
+    }
+}
+
+/// Recover `SourceCodeInfo` for `file` from its source text.
+///
+/// Only declarations that can be matched by name to an element of `file` get a location.
+pub fn from_source(source: &str, file: &FileDescriptorProto) -> SourceCodeInfo {
+    let (tokens, comments) = tokenize(source);
+    let decls = Scanner {
+        tokens: &tokens,
+        pos: 0,
+    }
+    .body(Context::File);
+    let mut builder = Builder {
+        tokens: &tokens,
+        comments: &comments,
+        locations: Vec::new(),
+    };
+    builder.file(&decls, file);
+
+    let mut info = SourceCodeInfo::new();
+    info.location = builder.locations;
+    info
+}
+
+/// The location recorded for `path`, if any.
+pub fn find<'a>(file: &'a FileDescriptorProto, path: &[i32]) -> Option<&'a Location> {
+    file.source_code_info
+        .as_ref()?
+        .location
+        .iter()
+        .find(|l| l.path == path)
+}
+
+/// Location path of the message named `full_name` (without leading dot) in `file`.
+pub fn message_path(file: &FileDescriptorProto, full_name: &str) -> Option<Vec<i32>> {
+    let relative = strip_package(file, full_name)?;
+    let mut parts = relative.split('.');
+    let first = parts.next()?;
+    let mut index = index_of(&file.message_type, first, |m| m.name())?;
+    let mut path = vec![FILE_MESSAGE_TYPE, index];
+    let mut message = &file.message_type[index as usize];
+    for part in parts {
+        index = index_of(&message.nested_type, part, |m| m.name())?;
+        path.extend([MESSAGE_NESTED_TYPE, index]);
+        message = &message.nested_type[index as usize];
+    }
+
+    Some(path)
+}
+
+/// Location path of the enum named `full_name` (without leading dot) in `file`.
+pub fn enum_path(file: &FileDescriptorProto, full_name: &str) -> Option<Vec<i32>> {
+    let relative = strip_package(file, full_name)?;
+    match relative.rsplit_once('.') {
+        None => index_of(&file.enum_type, relative, |e| e.name()).map(|i| vec![FILE_ENUM_TYPE, i]),
+        Some((parent, name)) => {
+            let package = file.package();
+            let parent = if package.is_empty() {
+                parent.to_owned()
+            } else {
+                format!("{}.{}", package, parent)
+            };
+            let mut path = message_path(file, &parent)?;
+            let mut message = &file.message_type[path[1] as usize];
+            for pair in path[2..].chunks(2) {
+                message = &message.nested_type[pair[1] as usize];
+            }
+            path.extend([
+                MESSAGE_ENUM_TYPE,
+                index_of(&message.enum_type, name, |e| e.name())?,
+            ]);
+            Some(path)
+        }
+    }
+}
+
+fn strip_package<'a>(file: &FileDescriptorProto, full_name: &'a str) -> Option<&'a str> {
+    if file.package().is_empty() {
+        Some(full_name)
+    } else {
+        full_name.strip_prefix(file.package())?.strip_prefix('.')
+    }
+}
+
+#[test]
+fn comments_follow_protoc_rules() {
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .include("testdata/comments")
+        .input("testdata/comments/greeter.proto")
+        .parse_and_typecheck()
+        .unwrap();
+    let mut file = parsed.file_descriptors.into_iter().next().unwrap();
+    let source = std::fs::read_to_string("testdata/comments/greeter.proto").unwrap();
+    file.source_code_info = Some(from_source(&source, &file)).into();
+
+    let syntax = find(&file, &[FILE_SYNTAX]).unwrap();
+    assert_eq!(syntax.leading_detached_comments, [" File header.\n"]);
+    assert_eq!(syntax.span, [2, 0, 18]);
+
+    let greeter = find(&file, &[FILE_SERVICE, 0]).unwrap();
+    assert_eq!(greeter.leading_comments(), " Leading for Greeter.\n");
+    assert_eq!(greeter.leading_detached_comments, [" Detached.\n"]);
+    assert_eq!(greeter.span, [9, 0, 12, 1]);
+
+    let hello = find(&file, &[FILE_SERVICE, 0, SERVICE_METHOD, 0]).unwrap();
+    assert_eq!(hello.leading_comments(), " Says hello.\n");
+    assert_eq!(hello.trailing_comments(), " Trailing for Hello.\n");
+
+    let ping = find(&file, &message_path(&file, "demo.Ping").unwrap()).unwrap();
+    assert_eq!(ping.leading_comments(), " Block\n comment. ");
+
+    let text = find(&file, &[FILE_MESSAGE_TYPE, 0, MESSAGE_FIELD, 0]).unwrap();
+    assert_eq!(text.trailing_comments(), " Text to echo.\n");
+    assert_eq!(text.span, [17, 2, 39]);
+    let number = find(&file, &[FILE_MESSAGE_TYPE, 0, MESSAGE_FIELD, 1]).unwrap();
+    assert_eq!(number.span, [19, 4, 21]);
+}
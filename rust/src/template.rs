@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+//! JSON templates of messages, as a starting point for writing requests.
+
+use protobuf::reflect::{MessageDescriptor, RuntimeFieldType, RuntimeType};
+use serde_json::{Map, Value};
+
+use crate::json;
+
+/// A JSON object with every field of `descriptor` set to a sample value.
+///
+/// Only the first member of each oneof is set, and repeated and map fields get a single
+/// element. A message type is expanded at most `recursion_limit` times on any path from the
+/// root; deeper occurrences are left empty, so self-referential messages stay finite.
+pub fn template(descriptor: &MessageDescriptor, recursion_limit: u32) -> Value {
+    Template {
+        recursion_limit,
+        stack: Vec::new(),
+    }
+    .message(descriptor)
+}
+
+struct Template {
+    recursion_limit: u32,
+    /// Full names of the messages being expanded, outermost first.
+    stack: Vec<String>,
+}
+
+impl Template {
+    fn message(&mut self, descriptor: &MessageDescriptor) -> Value {
+        if let Some(value) = well_known(descriptor) {
+            return value;
+        }
+        let full_name = descriptor.full_name().to_owned();
+        let expanded = self.stack.iter().filter(|n| **n == full_name).count();
+        if expanded as u32 >= self.recursion_limit {
+            return Value::Object(Map::new());
+        }
+
+        self.stack.push(full_name);
+        let mut object = Map::new();
+        for field in descriptor.fields() {
+            if let Some(oneof) = field.containing_oneof() {
+                if oneof.fields().next().map(|f| f.number()) != Some(field.number()) {
+                    continue;
+                }
+            }
+            let value = match field.runtime_field_type() {
+                RuntimeFieldType::Singular(t) => self.value(&t),
+                RuntimeFieldType::Repeated(t) => Value::Array(vec![self.value(&t)]),
+                RuntimeFieldType::Map(k, v) => {
+                    let mut entries = Map::new();
+                    entries.insert(map_key(&k), self.value(&v));
+                    Value::Object(entries)
+                }
+            };
+            object.insert(field.json_name().to_owned(), value);
+        }
+        self.stack.pop();
+
+        Value::Object(object)
+    }
+
+    fn value(&mut self, t: &RuntimeType) -> Value {
+        match t {
+            RuntimeType::I32 | RuntimeType::U32 => Value::from(0),
+            RuntimeType::I64 | RuntimeType::U64 => Value::from("0"),
+            RuntimeType::F32 | RuntimeType::F64 => Value::from(0.0),
+            RuntimeType::Bool => Value::Bool(false),
+            RuntimeType::String | RuntimeType::VecU8 => Value::from(""),
+            RuntimeType::Enum(e) if e.full_name() == "google.protobuf.NullValue" => Value::Null,
+            RuntimeType::Enum(e) => Value::from(e.values().next().unwrap().name()),
+            RuntimeType::Message(m) => self.message(m),
+        }
+    }
+}
+
+fn map_key(t: &RuntimeType) -> String {
+    match t {
+        RuntimeType::String => "key".to_owned(),
+        RuntimeType::Bool => "false".to_owned(),
+        _ => "0".to_owned(),
+    }
+}
+
+/// Samples of the well-known types with a special JSON form.
+fn well_known(descriptor: &MessageDescriptor) -> Option<Value> {
+    Some(match descriptor.full_name() {
+        "google.protobuf.Timestamp" => Value::from(json::format_timestamp(0, 0).unwrap()),
+        "google.protobuf.Duration" => Value::from("0s"),
+        "google.protobuf.FieldMask" => Value::from(""),
+        "google.protobuf.Struct" => Value::Object(Map::new()),
+        "google.protobuf.ListValue" => Value::Array(Vec::new()),
+        "google.protobuf.Value" => Value::Null,
+        "google.protobuf.Any" => {
+            let mut any = Map::new();
+            any.insert(
+                "@type".to_owned(),
+                Value::from("type.googleapis.com/google.protobuf.Empty"),
+            );
+            Value::Object(any)
+        }
+        "google.protobuf.DoubleValue" | "google.protobuf.FloatValue" => Value::from(0.0),
+        "google.protobuf.Int64Value" | "google.protobuf.UInt64Value" => Value::from("0"),
+        "google.protobuf.Int32Value" | "google.protobuf.UInt32Value" => Value::from(0),
+        "google.protobuf.BoolValue" => Value::Bool(false),
+        "google.protobuf.StringValue" | "google.protobuf.BytesValue" => Value::from(""),
+        _ => return None,
+    })
+}
+
+#[test]
+fn every_field_gets_a_sample() {
+    let (pool, descriptor) = crate::test_server::echo_message();
+
+    let value = template(&descriptor, 2);
+    let object = value.as_object().unwrap();
+    assert_eq!(object["id"], "0");
+    assert_eq!(object["mood"], "MOOD_UNSPECIFIED");
+    assert_eq!(object["numbers"], serde_json::json!([0]));
+    assert_eq!(object["scores"], serde_json::json!({ "key": "0" }));
+    assert_eq!(object["labels"], serde_json::json!({ "0": "" }));
+    assert_eq!(object["at"], "1970-01-01T00:00:00Z");
+    assert_eq!(object["wrapped"], 0);
+    // One oneof member only, and `maybe` is a proto3 optional, not a oneof.
+    assert!(object.contains_key("name") && !object.contains_key("code"));
+    assert_eq!(object["maybe"], 0);
+    // `child` is expanded once more, then left empty.
+    assert_eq!(object["child"]["child"], serde_json::json!({}));
+    assert_eq!(template(&descriptor, 1)["child"], serde_json::json!({}));
+
+    json::from_value(&descriptor, &value, &pool).unwrap();
+}
@@ -0,0 +1,278 @@
+//! In-process servers for tests.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{HeaderMap, Request, Response};
+use p12_keystore::{Certificate, KeyStore, KeyStoreEntry, PrivateKeyChain};
+use protobuf::descriptor::FileDescriptorSet;
+use protobuf::reflect::MessageDescriptor;
+use protobuf::Message;
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use rustls_pki_types::PrivatePkcs8KeyDer;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::server::Router;
+use tonic::transport::Server;
+
+use crate::grpc::runtime;
+use crate::pool::DescriptorPool;
+
+/// Serve `router` on a free local port, returning `host:port`.
+fn serve(router: Router) -> String {
+    let listener = runtime()
+        .block_on(TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    runtime().spawn(router.serve_with_incoming(TcpListenerStream::new(listener)));
+
+    address
+}
+
+/// Descriptors of `path` and everything it imports, parsed with the pure parser.
+pub fn descriptor_set(include: &str, path: &str) -> Vec<u8> {
+    let parsed = protobuf_parse::Parser::new()
+        .pure()
+        .include(include)
+        .include(crate::bundled::include_dir().unwrap())
+        .input(path)
+        .parse_and_typecheck()
+        .unwrap();
+    let mut set = FileDescriptorSet::new();
+    set.file = parsed.file_descriptors;
+
+    set.write_to_bytes().unwrap()
+}
+
+/// The pool of `testdata/echo/echo.proto` and its `echo.v1.EchoMessage`, which has a field
+/// of every kind.
+pub fn echo_message() -> (DescriptorPool, MessageDescriptor) {
+    let set = descriptor_set("testdata/echo", "testdata/echo/echo.proto");
+    let pool = DescriptorPool::from_descriptor_set(&set).unwrap();
+    let descriptor = pool.message_by_name("echo.v1.EchoMessage").unwrap();
+
+    (pool, descriptor)
+}
+
+/// A server exposing reflection for `descriptor_set`, through the v1 service or only
+/// through the older v1alpha one.
+pub fn reflection_server(descriptor_set: &[u8], v1alpha_only: bool) -> String {
+    let builder = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(descriptor_set);
+    let router = if v1alpha_only {
+        Server::builder().add_service(builder.build_v1alpha().unwrap())
+    } else {
+        Server::builder().add_service(builder.build_v1().unwrap())
+    };
+
+    serve(router)
+}
+
+/// A gRPC server for the `echo.v1.Echo` service of `testdata/echo/echo.proto`, written
+/// directly on HTTP/2 so that it returns request messages exactly as they were sent.
+///
+/// Request metadata steers it: `echo-status` and `echo-message` set the status calls end
+/// with, and no message is sent back unless it is OK, while `echo-details-bin` is returned
+/// as `grpc-status-details-bin`; `echo-repeat` is how many times
+/// `ServerStream` returns each message, 3 by default; `echo-delay-ms` is how long to wait
+/// before sending any message but the first, and `echo-stall-ms` how long to wait before
+/// answering at all. Request headers starting with `x-` are sent back as response headers,
+/// the `:authority` of the request as `echo-authority`, its `grpc-timeout` as
+/// `echo-timeout` and its `grpc-accept-encoding` as `echo-accept-encoding`. Messages keep
+/// the `grpc-encoding` of the request.
+pub fn echo_server() -> String {
+    spawn_echo_server(None)
+}
+
+/// Like [`echo_server`], over TLS with client certificates required.
+///
+/// Certificates are generated into `dir`: `ca.pem`, the authority signing the others;
+/// `client.pem` and `client.key`, and the same in `client.p12` with the password `secret`.
+/// The server certificate is only valid for `grpc.internal`.
+pub fn tls_echo_server(dir: &Path) -> String {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let signed = |names: Vec<String>| {
+        let key = KeyPair::generate().unwrap();
+        let certificate = CertificateParams::new(names)
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        (certificate, key)
+    };
+    let (server, server_key) = signed(vec!["grpc.internal".to_owned()]);
+    let (client, client_key) = signed(vec!["client".to_owned()]);
+
+    std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+    std::fs::write(dir.join("client.pem"), client.pem()).unwrap();
+    std::fs::write(dir.join("client.key"), client_key.serialize_pem()).unwrap();
+    let mut store = KeyStore::new();
+    let chain = [client.der(), ca.der()].map(|c| Certificate::from_der(c).unwrap());
+    let key_chain = PrivateKeyChain::new(client_key.serialize_der(), [1], chain);
+    store.add_entry("client", KeyStoreEntry::PrivateKeyChain(key_chain));
+    std::fs::write(
+        dir.join("client.p12"),
+        store.writer("secret").write().unwrap(),
+    )
+    .unwrap();
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.der().clone()).unwrap();
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .unwrap();
+    let key = PrivatePkcs8KeyDer::from(server_key.serialize_der());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(vec![server.der().clone()], key.into())
+        .unwrap();
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    spawn_echo_server(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+fn spawn_echo_server(tls: Option<TlsAcceptor>) -> String {
+    let listener = runtime()
+        .block_on(TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    runtime().spawn(async move {
+        while let Ok((tcp, _)) = listener.accept().await {
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                match tls {
+                    None => serve_echo(tcp).await,
+                    Some(acceptor) => {
+                        if let Ok(stream) = acceptor.accept(tcp).await {
+                            serve_echo(stream).await;
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    address
+}
+
+async fn serve_echo<T: AsyncRead + AsyncWrite + Unpin>(io: T) {
+    let Ok(mut connection) = h2::server::handshake(io).await else {
+        return;
+    };
+    while let Some(Ok((request, respond))) = connection.accept().await {
+        tokio::spawn(echo(request, respond));
+    }
+}
+
+async fn echo(
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+) -> Result<(), h2::Error> {
+    let (parts, mut body) = request.into_parts();
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    };
+    let status: i32 = header("echo-status")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    let method = parts.uri.path().rsplit('/').next().unwrap_or_default();
+    let repeat = match method {
+        "ServerStream" => header("echo-repeat")
+            .and_then(|r| r.parse().ok())
+            .unwrap_or(3),
+        _ => 1,
+    };
+    let delay = Duration::from_millis(
+        header("echo-delay-ms")
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(0),
+    );
+    let stall = Duration::from_millis(
+        header("echo-stall-ms")
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(0),
+    );
+    let mut sent = 0;
+
+    tokio::time::sleep(stall).await;
+    let mut response = Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc");
+    for (name, value) in &parts.headers {
+        if name.as_str().starts_with("x-") {
+            response = response.header(name, value);
+        }
+    }
+    if let Some(authority) = parts.uri.authority() {
+        response = response.header("echo-authority", authority.as_str());
+    }
+    if let Some(timeout) = parts.headers.get("grpc-timeout") {
+        response = response.header("echo-timeout", timeout);
+    }
+    if let Some(encoding) = parts.headers.get("grpc-accept-encoding") {
+        response = response.header("echo-accept-encoding", encoding);
+    }
+    if let Some(encoding) = parts.headers.get("grpc-encoding") {
+        response = response.header("grpc-encoding", encoding);
+    }
+    let mut stream = respond.send_response(response.body(()).unwrap(), false)?;
+
+    let mut buffer = BytesMut::new();
+    let mut last = None;
+    while let Some(data) = body.data().await {
+        let data = data?;
+        let _ = body.flow_control().release_capacity(data.len());
+        buffer.extend_from_slice(&data);
+        while buffer.len() >= 5 {
+            let len = u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize;
+            if buffer.len() < 5 + len {
+                break;
+            }
+            let frame = buffer.split_to(5 + len).freeze();
+            if status != 0 {
+                continue;
+            }
+            if method == "ClientStream" {
+                last = Some(frame);
+            } else {
+                for _ in 0..repeat {
+                    if sent > 0 {
+                        tokio::time::sleep(delay).await;
+                    }
+                    stream.send_data(frame.clone(), false)?;
+                    sent += 1;
+                }
+            }
+        }
+    }
+    if let Some(frame) = last {
+        stream.send_data(frame, false)?;
+    }
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", status.into());
+    if let Some(message) = header("echo-message") {
+        trailers.insert("grpc-message", message.parse().unwrap());
+    }
+    if let Some(details) = parts.headers.get("echo-details-bin") {
+        trailers.insert("grpc-status-details-bin", details.clone());
+    }
+    stream.send_trailers(trailers)
+}
@@ -0,0 +1,844 @@
+#![allow(dead_code)]
+
+//! Protobuf text format, as read and written by `protoc --encode` / `--decode`.
+//!
+//! rust-protobuf ships a text format parser, but it lacks list values, enum numbers and
+//! `Any` expansion, and doesn't expose where an error is; this one covers the syntax of
+//! <https://protobuf.dev/reference/protobuf/textformat-spec/> minus extensions.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Write;
+
+use protobuf::descriptor::field_descriptor_proto::Type;
+use protobuf::reflect::{
+    FieldDescriptor, MessageDescriptor, ReflectFieldRef, ReflectValueBox, ReflectValueRef,
+    RuntimeFieldType, RuntimeType,
+};
+use protobuf::{MessageDyn, UnknownValueRef};
+
+use crate::codec;
+use crate::json;
+use crate::pool::DescriptorPool;
+
+/// An error in text format input, at a 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextError {
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for TextError {}
+
+type TextResult<T> = Result<T, TextError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Ident,
+    Number,
+    String,
+    Symbol,
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    text: String,
+    /// Decoded value of a string literal.
+    bytes: Vec<u8>,
+    line: u32,
+    column: u32,
+}
+
+impl Token {
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.kind == Kind::Symbol && self.text.starts_with(symbol)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> TextResult<T> {
+        Err(TextError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        })
+    }
+}
+
+fn tokenize(text: &str) -> TextResult<Vec<Token>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut column) = (0, 1, 1);
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = (line, column);
+        let token = |kind, text: String, bytes| Token {
+            kind,
+            text,
+            bytes,
+            line: start.0,
+            column: start.1,
+        };
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            column = 1;
+        } else if c.is_whitespace() {
+            i += 1;
+            column += 1;
+        } else if c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = (i..chars.len())
+                .find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '_'))
+                .unwrap_or(chars.len());
+            tokens.push(token(
+                Kind::Ident,
+                chars[i..end].iter().collect(),
+                Vec::new(),
+            ));
+            column += (end - i) as u32;
+            i = end;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()))
+        {
+            let mut end = i;
+            while end < chars.len() {
+                let c = chars[end];
+                let exponent_sign = matches!(c, '+' | '-')
+                    && matches!(chars[end - 1], 'e' | 'E')
+                    && !chars[i..end].iter().any(|c| matches!(c, 'x' | 'X'));
+                if !(c.is_ascii_alphanumeric() || c == '.' || exponent_sign) {
+                    break;
+                }
+                end += 1;
+            }
+            tokens.push(token(
+                Kind::Number,
+                chars[i..end].iter().collect(),
+                Vec::new(),
+            ));
+            column += (end - i) as u32;
+            i = end;
+        } else if c == '"' || c == '\'' {
+            let (bytes, end) = match unescape(&chars, i + 1, c) {
+                Some(literal) => literal,
+                None => {
+                    return token(Kind::String, String::new(), Vec::new())
+                        .error("unterminated or malformed string")
+                }
+            };
+            tokens.push(token(Kind::String, chars[i..end].iter().collect(), bytes));
+            column += (end - i) as u32;
+            i = end;
+        } else {
+            tokens.push(token(Kind::Symbol, c.to_string(), Vec::new()));
+            i += 1;
+            column += 1;
+        }
+    }
+    tokens.push(Token {
+        kind: Kind::End,
+        text: String::new(),
+        bytes: Vec::new(),
+        line,
+        column,
+    });
+
+    Ok(tokens)
+}
+
+/// Decode a string literal whose content starts at `start`, returning its bytes and the
+/// index after the closing quote.
+fn unescape(chars: &[char], start: usize, quote: char) -> Option<(Vec<u8>, usize)> {
+    let mut bytes = Vec::new();
+    let mut i = start;
+    loop {
+        let c = *chars.get(i)?;
+        i += 1;
+        match c {
+            '\n' => return None,
+            c if c == quote => return Some((bytes, i)),
+            '\\' => {
+                let c = *chars.get(i)?;
+                i += 1;
+                match c {
+                    'n' => bytes.push(b'\n'),
+                    'r' => bytes.push(b'\r'),
+                    't' => bytes.push(b'\t'),
+                    'a' => bytes.push(7),
+                    'b' => bytes.push(8),
+                    'f' => bytes.push(12),
+                    'v' => bytes.push(11),
+                    '0'..='7' => {
+                        let end = (i..(i + 2).min(chars.len()))
+                            .find(|&j| !('0'..='7').contains(&chars[j]))
+                            .unwrap_or((i + 2).min(chars.len()));
+                        let digits: String = chars[i - 1..end].iter().collect();
+                        bytes.push(u8::from_str_radix(&digits, 8).ok()?);
+                        i = end;
+                    }
+                    'x' | 'X' => {
+                        let end = (i..(i + 2).min(chars.len()))
+                            .find(|&j| !chars[j].is_ascii_hexdigit())
+                            .unwrap_or((i + 2).min(chars.len()));
+                        let digits: String = chars[i..end].iter().collect();
+                        bytes.push(u8::from_str_radix(&digits, 16).ok()?);
+                        i = end;
+                    }
+                    'u' | 'U' => {
+                        let len = if c == 'u' { 4 } else { 8 };
+                        let digits: String = chars.get(i..i + len)?.iter().collect();
+                        let c = char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?;
+                        bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        i += len;
+                    }
+                    c => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                }
+            }
+            c => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+}
+
+/// Parse `text` into a message of type `descriptor`.
+///
+/// `pool` resolves the types of expanded `Any` values.
+pub fn parse(
+    descriptor: &MessageDescriptor,
+    text: &str,
+    pool: &DescriptorPool,
+) -> TextResult<Box<dyn MessageDyn>> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        pool,
+    };
+    let mut message = descriptor.new_instance();
+    parser.merge(&mut *message, None)?;
+
+    Ok(message)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    pool: &'a DescriptorPool,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != Kind::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn next_if_symbol(&mut self, symbol: char) -> bool {
+        let found = self.peek().is_symbol(symbol);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> TextResult<()> {
+        if self.next_if_symbol(symbol) {
+            Ok(())
+        } else {
+            self.peek().error(format!("expected `{}`", symbol))
+        }
+    }
+
+    /// Read fields into `message` until `end` or, at the top level, the end of the input.
+    fn merge(&mut self, message: &mut dyn MessageDyn, end: Option<char>) -> TextResult<()> {
+        let descriptor = message.descriptor_dyn();
+        let mut set = HashSet::new();
+        let mut oneofs: HashMap<String, String> = HashMap::new();
+        loop {
+            let token = self.peek().clone();
+            match end {
+                Some(end) if token.is_symbol(end) => {
+                    self.next();
+                    return Ok(());
+                }
+                Some(end) if token.kind == Kind::End => {
+                    return token.error(format!("expected `{}`", end));
+                }
+                None if token.kind == Kind::End => return Ok(()),
+                _ => {}
+            }
+
+            if token.is_symbol('[') {
+                self.any(message)?;
+            } else {
+                if token.kind != Kind::Ident {
+                    return token.error("expected a field name");
+                }
+                self.next();
+                let Some(field) = find_field(&descriptor, &token.text) else {
+                    return token.error(format!(
+                        "unknown field `{}` in {}",
+                        token.text,
+                        descriptor.full_name()
+                    ));
+                };
+                if !field.is_repeated_or_map() && !set.insert(field.number()) {
+                    return token.error(format!("`{}` is set more than once", token.text));
+                }
+                if let Some(oneof) = field.containing_oneof() {
+                    if let Some(other) = oneofs.insert(oneof.name().to_owned(), token.text.clone())
+                    {
+                        return token.error(format!(
+                            "`{}` and `{}` belong to the same oneof `{}`",
+                            other,
+                            token.text,
+                            oneof.name()
+                        ));
+                    }
+                }
+                self.field_value(message, &field)?;
+            }
+
+            if !self.next_if_symbol(',') {
+                self.next_if_symbol(';');
+            }
+        }
+    }
+
+    /// An expanded `Any`: `[type.googleapis.com/package.Message] { ... }`.
+    fn any(&mut self, message: &mut dyn MessageDyn) -> TextResult<()> {
+        let open = self.next();
+        let mut name = String::new();
+        while !self.peek().is_symbol(']') {
+            let token = self.next();
+            if !matches!(token.kind, Kind::Ident | Kind::Symbol) || token.is_symbol('[') {
+                return token.error("expected `]`");
+            }
+            name.push_str(&token.text);
+        }
+        self.next();
+        if !name.contains('/') {
+            return open.error("extensions are not supported");
+        }
+        if message.descriptor_dyn().full_name() != "google.protobuf.Any" {
+            return open.error(format!(
+                "`[{}]` can only be used in google.protobuf.Any",
+                name
+            ));
+        }
+        let Some(descriptor) = json::resolve_type_url(self.pool, &name) else {
+            return open.error(format!("unknown type `{}`", name));
+        };
+
+        self.next_if_symbol(':');
+        let packed = self.message(&descriptor)?;
+        let bytes = match codec::encode(&*packed) {
+            Ok(bytes) => bytes,
+            Err(e) => return open.error(e.to_string()),
+        };
+        let descriptor = message.descriptor_dyn();
+        descriptor
+            .field_by_name("type_url")
+            .unwrap()
+            .set_singular_field(message, ReflectValueBox::String(name));
+        descriptor
+            .field_by_name("value")
+            .unwrap()
+            .set_singular_field(message, ReflectValueBox::Bytes(bytes));
+
+        Ok(())
+    }
+
+    fn field_value(
+        &mut self,
+        message: &mut dyn MessageDyn,
+        field: &FieldDescriptor,
+    ) -> TextResult<()> {
+        let is_message = matches!(field.proto().type_(), Type::TYPE_MESSAGE | Type::TYPE_GROUP);
+        if !self.next_if_symbol(':') && !is_message {
+            return self.peek().error("expected `:`");
+        }
+
+        let token = self.peek().clone();
+        if !token.is_symbol('[') {
+            return self.add_value(message, field);
+        }
+        if !field.is_repeated_or_map() {
+            return token.error(format!("`{}` is not repeated", field.name()));
+        }
+        self.next();
+        if self.next_if_symbol(']') {
+            return Ok(());
+        }
+        loop {
+            self.add_value(message, field)?;
+            if !self.next_if_symbol(',') {
+                return self.expect_symbol(']');
+            }
+        }
+    }
+
+    fn add_value(
+        &mut self,
+        message: &mut dyn MessageDyn,
+        field: &FieldDescriptor,
+    ) -> TextResult<()> {
+        match field.runtime_field_type() {
+            RuntimeFieldType::Singular(t) => {
+                let value = self.value(&t)?;
+                field.set_singular_field(message, value);
+            }
+            RuntimeFieldType::Repeated(t) => {
+                let value = self.value(&t)?;
+                field.mut_repeated(message).push(value);
+            }
+            RuntimeFieldType::Map(key_type, value_type) => {
+                let (key, value) = self.map_entry(&key_type, &value_type)?;
+                field.mut_map(message).insert(key, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Consume the opening brace of a message, returning the matching closing one.
+    fn open(&mut self) -> TextResult<char> {
+        match self.next() {
+            t if t.is_symbol('{') => Ok('}'),
+            t if t.is_symbol('<') => Ok('>'),
+            t => t.error("expected `{`"),
+        }
+    }
+
+    fn message(&mut self, descriptor: &MessageDescriptor) -> TextResult<Box<dyn MessageDyn>> {
+        let end = self.open()?;
+        let mut message = descriptor.new_instance();
+        self.merge(&mut *message, Some(end))?;
+
+        Ok(message)
+    }
+
+    /// A map entry, `{ key: ... value: ... }`, either part defaulting when left out.
+    ///
+    /// Entry types can't be instantiated through reflection, so this doesn't use `message`.
+    fn map_entry(
+        &mut self,
+        key_type: &RuntimeType,
+        value_type: &RuntimeType,
+    ) -> TextResult<(ReflectValueBox, ReflectValueBox)> {
+        let end = self.open()?;
+        let (mut key, mut value) = (None, None);
+        while !self.next_if_symbol(end) {
+            let token = self.next();
+            let (part, t) = match token.text.as_str() {
+                "key" if token.kind == Kind::Ident => (&mut key, key_type),
+                "value" if token.kind == Kind::Ident => (&mut value, value_type),
+                _ if token.kind == Kind::End => return token.error(format!("expected `{}`", end)),
+                _ => return token.error("expected `key` or `value`"),
+            };
+            if part.is_some() {
+                return token.error(format!("`{}` is set more than once", token.text));
+            }
+            if !self.next_if_symbol(':') && !matches!(t, RuntimeType::Message(_)) {
+                return self.peek().error("expected `:`");
+            }
+            *part = Some(self.value(t)?);
+            if !self.next_if_symbol(',') {
+                self.next_if_symbol(';');
+            }
+        }
+
+        Ok((
+            key.unwrap_or_else(|| default_value(key_type)),
+            value.unwrap_or_else(|| default_value(value_type)),
+        ))
+    }
+
+    fn value(&mut self, t: &RuntimeType) -> TextResult<ReflectValueBox> {
+        let token = self.peek().clone();
+        Ok(match t {
+            RuntimeType::Message(m) => ReflectValueBox::Message(self.message(m)?),
+            RuntimeType::String => match String::from_utf8(self.string()?) {
+                Ok(s) => ReflectValueBox::String(s),
+                Err(_) => return token.error("string is not valid UTF-8"),
+            },
+            RuntimeType::VecU8 => ReflectValueBox::Bytes(self.string()?),
+            RuntimeType::Bool => {
+                let token = self.next();
+                match token.text.as_str() {
+                    "true" | "True" | "t" | "1" => ReflectValueBox::Bool(true),
+                    "false" | "False" | "f" | "0" => ReflectValueBox::Bool(false),
+                    _ => return token.error("expected true or false"),
+                }
+            }
+            RuntimeType::Enum(e) if token.kind == Kind::Ident => {
+                self.next();
+                match e.value_by_name(&token.text) {
+                    Some(v) => ReflectValueBox::Enum(e.clone(), v.value()),
+                    None => {
+                        return token.error(format!(
+                            "`{}` is not a value of enum {}",
+                            token.text,
+                            e.full_name()
+                        ))
+                    }
+                }
+            }
+            RuntimeType::Enum(e) => ReflectValueBox::Enum(e.clone(), self.integer("enum")?),
+            RuntimeType::I32 => ReflectValueBox::I32(self.integer("int32")?),
+            RuntimeType::I64 => ReflectValueBox::I64(self.integer("int64")?),
+            RuntimeType::U32 => ReflectValueBox::U32(self.integer("uint32")?),
+            RuntimeType::U64 => ReflectValueBox::U64(self.integer("uint64")?),
+            RuntimeType::F32 => ReflectValueBox::F32(self.float()? as f32),
+            RuntimeType::F64 => ReflectValueBox::F64(self.float()?),
+        })
+    }
+
+    /// One or more adjacent string literals.
+    fn string(&mut self) -> TextResult<Vec<u8>> {
+        if self.peek().kind != Kind::String {
+            return self.peek().error("expected a string");
+        }
+        let mut bytes = Vec::new();
+        while self.peek().kind == Kind::String {
+            bytes.extend(self.next().bytes);
+        }
+
+        Ok(bytes)
+    }
+
+    fn integer<T: TryFrom<i128>>(&mut self, type_name: &str) -> TextResult<T> {
+        let start = self.peek().clone();
+        let negative = self.next_if_symbol('-');
+        let token = self.next();
+        let text = token.text.as_str();
+        let magnitude = if token.kind != Kind::Number {
+            None
+        } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            u128::from_str_radix(hex, 16).ok()
+        } else if text.len() > 1 && text.starts_with('0') {
+            u128::from_str_radix(&text[1..], 8).ok()
+        } else {
+            text.parse().ok()
+        };
+        let Some(magnitude) = magnitude.and_then(|m| i128::try_from(m).ok()) else {
+            return token.error(format!("expected an integer ({})", type_name));
+        };
+
+        let value = if negative { -magnitude } else { magnitude };
+        T::try_from(value)
+            .or_else(|_| start.error(format!("{} is out of range for {}", value, type_name)))
+    }
+
+    fn float(&mut self) -> TextResult<f64> {
+        let negative = self.next_if_symbol('-');
+        let token = self.next();
+        let value = match token.kind {
+            Kind::Number => token.text.trim_end_matches(['f', 'F']).parse::<f64>().ok(),
+            Kind::Ident => match token.text.to_ascii_lowercase().as_str() {
+                "inf" | "infinity" => Some(f64::INFINITY),
+                "nan" => Some(f64::NAN),
+                _ => None,
+            },
+            _ => None,
+        };
+        match value {
+            Some(value) if negative => Ok(-value),
+            Some(value) => Ok(value),
+            None => token.error("expected a number"),
+        }
+    }
+}
+
+/// A field by name, groups being named after their type.
+fn find_field(descriptor: &MessageDescriptor, name: &str) -> Option<FieldDescriptor> {
+    descriptor.field_by_name(name).or_else(|| {
+        descriptor.fields().find(|f| {
+            f.proto().type_() == Type::TYPE_GROUP
+                && f.proto().type_name().rsplit('.').next() == Some(name)
+        })
+    })
+}
+
+fn default_value(t: &RuntimeType) -> ReflectValueBox {
+    match t {
+        RuntimeType::I32 => ReflectValueBox::I32(0),
+        RuntimeType::I64 => ReflectValueBox::I64(0),
+        RuntimeType::U32 => ReflectValueBox::U32(0),
+        RuntimeType::U64 => ReflectValueBox::U64(0),
+        RuntimeType::F32 => ReflectValueBox::F32(0.0),
+        RuntimeType::F64 => ReflectValueBox::F64(0.0),
+        RuntimeType::Bool => ReflectValueBox::Bool(false),
+        RuntimeType::String => ReflectValueBox::String(String::new()),
+        RuntimeType::VecU8 => ReflectValueBox::Bytes(Vec::new()),
+        RuntimeType::Enum(e) => ReflectValueBox::Enum(e.clone(), e.default_value().value()),
+        RuntimeType::Message(m) => ReflectValueBox::Message(m.new_instance()),
+    }
+}
+
+/// Print `message` in text format, one field per line.
+///
+/// `Any` values whose type is in `pool` are expanded.
+pub fn print(message: &dyn MessageDyn, pool: &DescriptorPool) -> String {
+    let mut out = String::new();
+    Printer { pool }.message(message, 0, &mut out);
+
+    out
+}
+
+struct Printer<'a> {
+    pool: &'a DescriptorPool,
+}
+
+impl<'a> Printer<'a> {
+    fn message(&self, message: &dyn MessageDyn, indent: usize, out: &mut String) {
+        if self.any(message, indent, out) {
+            return;
+        }
+
+        let descriptor = message.descriptor_dyn();
+        for field in descriptor.fields() {
+            let name = match field.proto().type_() {
+                Type::TYPE_GROUP => field
+                    .proto()
+                    .type_name()
+                    .rsplit('.')
+                    .next()
+                    .unwrap()
+                    .to_owned(),
+                _ => field.name().to_owned(),
+            };
+            match field.get_reflect(message) {
+                ReflectFieldRef::Optional(value) => {
+                    if let Some(value) = value.value() {
+                        if codec::has_presence(&field) || !codec::is_default(&value) {
+                            self.field(&name, &value, indent, out);
+                        }
+                    }
+                }
+                ReflectFieldRef::Repeated(values) => {
+                    for value in &values {
+                        self.field(&name, &value, indent, out);
+                    }
+                }
+                ReflectFieldRef::Map(map) => {
+                    for (key, value) in &map {
+                        writeln!(out, "{:indent$}{} {{", "", name, indent = indent * 2).unwrap();
+                        self.field("key", &key, indent + 1, out);
+                        self.field("value", &value, indent + 1, out);
+                        writeln!(out, "{:indent$}}}", "", indent = indent * 2).unwrap();
+                    }
+                }
+            }
+        }
+
+        let mut unknown: Vec<(u32, UnknownValueRef)> = message
+            .special_fields_dyn()
+            .unknown_fields()
+            .iter()
+            .collect();
+        unknown.sort_by_key(|(number, _)| *number);
+        for (number, value) in unknown {
+            let value = match value {
+                UnknownValueRef::Varint(v) | UnknownValueRef::Fixed64(v) => v.to_string(),
+                UnknownValueRef::Fixed32(v) => v.to_string(),
+                UnknownValueRef::LengthDelimited(v) => quote(v, false),
+            };
+            writeln!(
+                out,
+                "{:indent$}{}: {}",
+                "",
+                number,
+                value,
+                indent = indent * 2
+            )
+            .unwrap();
+        }
+    }
+
+    /// Print an `Any` holding a known type in its expanded form.
+    fn any(&self, message: &dyn MessageDyn, indent: usize, out: &mut String) -> bool {
+        let descriptor = message.descriptor_dyn();
+        if descriptor.full_name() != "google.protobuf.Any" {
+            return false;
+        }
+        let get = |name| {
+            descriptor
+                .field_by_name(name)
+                .unwrap()
+                .get_singular_field_or_default(message)
+        };
+        let type_url = get("type_url").to_str().unwrap().to_owned();
+        let Some(packed) = json::resolve_type_url(self.pool, &type_url)
+            .and_then(|d| d.parse_from_bytes(get("value").to_bytes().unwrap()).ok())
+        else {
+            return false;
+        };
+
+        writeln!(out, "{:indent$}[{}] {{", "", type_url, indent = indent * 2).unwrap();
+        self.message(&*packed, indent + 1, out);
+        writeln!(out, "{:indent$}}}", "", indent = indent * 2).unwrap();
+
+        true
+    }
+
+    fn field(&self, name: &str, value: &ReflectValueRef, indent: usize, out: &mut String) {
+        let value = match value {
+            ReflectValueRef::Message(m) => {
+                writeln!(out, "{:indent$}{} {{", "", name, indent = indent * 2).unwrap();
+                self.message(&**m, indent + 1, out);
+                writeln!(out, "{:indent$}}}", "", indent = indent * 2).unwrap();
+                return;
+            }
+            ReflectValueRef::F32(v) => float(*v as f64, v.to_string()),
+            ReflectValueRef::F64(v) => float(*v, v.to_string()),
+            ReflectValueRef::String(v) => quote(v.as_bytes(), true),
+            ReflectValueRef::Bytes(v) => quote(v, false),
+            ReflectValueRef::Enum(e, n) => match e.value_by_number(*n) {
+                Some(v) => v.name().to_owned(),
+                None => n.to_string(),
+            },
+            ReflectValueRef::I32(v) => v.to_string(),
+            ReflectValueRef::I64(v) => v.to_string(),
+            ReflectValueRef::U32(v) => v.to_string(),
+            ReflectValueRef::U64(v) => v.to_string(),
+            ReflectValueRef::Bool(v) => v.to_string(),
+        };
+        writeln!(
+            out,
+            "{:indent$}{}: {}",
+            "",
+            name,
+            value,
+            indent = indent * 2
+        )
+        .unwrap();
+    }
+}
+
+/// `shortest` is the shortest decimal form of the value, as printed by Rust.
+fn float(value: f64, shortest: String) -> String {
+    if value.is_nan() {
+        "nan".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else {
+        shortest
+    }
+}
+
+/// A quoted string literal. Text keeps its non-ASCII characters, bytes escape them.
+fn quote(bytes: &[u8], text: bool) -> String {
+    let mut quoted = String::from("\"");
+    if text {
+        for c in String::from_utf8_lossy(bytes).chars() {
+            match c {
+                c if (c as u32) < 0x80 => escape_byte(c as u8, &mut quoted),
+                c => quoted.push(c),
+            }
+        }
+    } else {
+        for &b in bytes {
+            escape_byte(b, &mut quoted);
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+fn escape_byte(b: u8, out: &mut String) {
+    match b {
+        b'\n' => out.push_str("\\n"),
+        b'\r' => out.push_str("\\r"),
+        b'\t' => out.push_str("\\t"),
+        b'"' => out.push_str("\\\""),
+        b'\'' => out.push_str("\\'"),
+        b'\\' => out.push_str("\\\\"),
+        0x20..=0x7e => out.push(b as char),
+        b => write!(out, "\\{:03o}", b).unwrap(),
+    }
+}
+
+#[test]
+fn text_format_round_trip() {
+    let (pool, descriptor) = crate::test_server::echo_message();
+
+    let message = parse(
+        &descriptor,
+        r#"
+        # Comments and both separators are allowed.
+        text: "caf\303\251 " 'ok';
+        id: -0x10, ratio: -inf
+        payload: "\x01\377"
+        mood: 2
+        numbers: [1, 2] numbers: 3
+        scores { key: "a" value: 1 }
+        code: 0
+        child < flag: true >
+        detail {
+          [type.googleapis.com/google.protobuf.Duration] { seconds: 3 }
+        }
+        "#,
+        &pool,
+    )
+    .unwrap();
+    let expected = r#"text: "café ok"
+id: -16
+ratio: -inf
+payload: "\001\377"
+mood: GRUMPY
+numbers: 1
+numbers: 2
+numbers: 3
+scores {
+  key: "a"
+  value: 1
+}
+code: 0
+child {
+  flag: true
+}
+detail {
+  [type.googleapis.com/google.protobuf.Duration] {
+    seconds: 3
+  }
+}
+"#;
+    let printed = print(&*message, &pool);
+    assert_eq!(printed, expected);
+    assert_eq!(
+        print(&*parse(&descriptor, &printed, &pool).unwrap(), &pool),
+        expected
+    );
+
+    let error = |text: &str| parse(&descriptor, text, &pool).unwrap_err().to_string();
+    assert_eq!(
+        error("text: \"a\"\n  nope: 1"),
+        "2:3: unknown field `nope` in echo.v1.EchoMessage"
+    );
+    assert_eq!(error("count: -1"), "1:8: -1 is out of range for uint32");
+    assert_eq!(error("child {\n  text: 1"), "2:9: expected a string");
+    assert_eq!(error("child { text: \"a\""), "1:18: expected `}`");
+    assert_eq!(
+        error("text: \"a\" text: \"b\""),
+        "1:11: `text` is set more than once"
+    );
+    assert_eq!(error("text \"a\""), "1:6: expected `:`");
+}
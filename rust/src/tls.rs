@@ -0,0 +1,185 @@
+#![allow(dead_code)]
+
+//! TLS for connections to servers, with rustls.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use p12_keystore::KeyStore;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::api::{ClientCertificate, TlsSettings};
+
+/// Run the TLS handshake with `host` over `tcp`.
+pub async fn connect(
+    tcp: TcpStream,
+    host: &str,
+    settings: &TlsSettings,
+) -> Result<TlsStream<TcpStream>> {
+    let config = client_config(settings)?;
+    let server_name = ServerName::try_from(host.to_owned())
+        .with_context(|| format!("`{}` is not a valid server name", host))?;
+
+    TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .with_context(|| format!("TLS handshake with `{}` failed", host))
+}
+
+fn client_config(settings: &TlsSettings) -> Result<ClientConfig> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = if settings.insecure_skip_verify {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SkipVerification(provider)))
+    } else {
+        builder.with_root_certificates(roots(settings.ca_certificate_path.as_deref())?)
+    };
+
+    let mut config = match &settings.client_certificate {
+        None => builder.with_no_client_auth(),
+        Some(certificate) => {
+            let (chain, key) = client_identity(certificate)?;
+            builder
+                .with_client_auth_cert(chain, key)
+                .context("invalid client certificate")?
+        }
+    };
+    config.alpn_protocols = vec![b"h2".to_vec()];
+
+    Ok(config)
+}
+
+/// The certificate authorities in the PEM file at `path`, or the system's.
+fn roots(path: Option<&str>) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match path {
+        Some(path) => {
+            let certificates = read_certificates(path)?;
+            if certificates.is_empty() {
+                bail!("no certificates in `{}`", path);
+            }
+            for certificate in certificates {
+                roots
+                    .add(certificate)
+                    .with_context(|| format!("invalid CA certificate in `{}`", path))?;
+            }
+        }
+        None => {
+            let native = rustls_native_certs::load_native_certs();
+            let (added, _) = roots.add_parsable_certificates(native.certs);
+            if added == 0 {
+                bail!("no CA certificates found on this system");
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read `{}`", path))
+}
+
+fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_slice_iter(&read(path)?)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("`{}` is not a PEM file", path))
+}
+
+/// Certificate chain, leaf first, and private key of `certificate`.
+fn client_identity(
+    certificate: &ClientCertificate,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    match certificate {
+        ClientCertificate::Pem {
+            certificate_path,
+            private_key_path,
+        } => {
+            let chain = read_certificates(certificate_path)?;
+            if chain.is_empty() {
+                bail!("no certificates in `{}`", certificate_path);
+            }
+            let key = PrivateKeyDer::from_pem_slice(&read(private_key_path)?)
+                .with_context(|| format!("no private key in `{}`", private_key_path))?;
+            Ok((chain, key))
+        }
+        ClientCertificate::Pkcs12 { path, password } => {
+            let store = KeyStore::from_pkcs12(&read(path)?, password).map_err(|e| {
+                anyhow!(
+                    "`{}` is not a PKCS#12 archive or the password is wrong: {}",
+                    path,
+                    e
+                )
+            })?;
+            let Some((_, key_chain)) = store.private_key_chain() else {
+                bail!("no private key in `{}`", path);
+            };
+            let chain = key_chain
+                .chain()
+                .iter()
+                .map(|c| CertificateDer::from(c.as_der().to_vec()))
+                .collect();
+            let key = PrivatePkcs8KeyDer::from(key_chain.key().to_vec());
+            Ok((chain, key.into()))
+        }
+    }
+}
+
+/// Accepts any server certificate, still checking that the server holds its key.
+#[derive(Debug)]
+struct SkipVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,443 @@
+#![allow(dead_code)]
+
+//! Checking a JSON body against its message type before it is sent.
+//!
+//! Unlike `json::parse`, which stops at the first problem, every problem is reported, each
+//! with the range of text it is about so the editor can underline it.
+
+use std::collections::HashMap;
+
+use protobuf::reflect::{MessageDescriptor, RuntimeFieldType, RuntimeType};
+use serde_json::{Map, Value};
+
+use crate::api::JsonDiagnostic;
+use crate::json::{self, pointer, JsonError};
+use crate::pool::DescriptorPool;
+
+/// Every problem with `text` as the JSON form of a `descriptor` message, in text order.
+///
+/// `pool` resolves the types named in `Any` values.
+pub fn validate(
+    descriptor: &MessageDescriptor,
+    text: &str,
+    pool: &DescriptorPool,
+) -> Vec<JsonDiagnostic> {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(e) => {
+            let offset = offset_at(text, e.line(), e.column());
+            return vec![JsonDiagnostic {
+                path: String::new(),
+                message: format!("invalid JSON: {}", e),
+                start: offset,
+                end: offset,
+            }];
+        }
+    };
+
+    let mut validator = Validator {
+        pool,
+        problems: Vec::new(),
+    };
+    validator.message(descriptor, &value, "");
+
+    let spans = spans(text);
+    let mut diagnostics: Vec<JsonDiagnostic> = validator
+        .problems
+        .into_iter()
+        .map(|Problem { error, on_key }| {
+            let span = spans.get(&error.path).copied().unwrap_or_default();
+            let (start, end) = match span.key {
+                Some(key) if on_key => key,
+                _ => span.value,
+            };
+            JsonDiagnostic {
+                path: error.path,
+                message: error.message,
+                start,
+                end,
+            }
+        })
+        .collect();
+    diagnostics.sort_by_key(|d| d.start);
+
+    diagnostics
+}
+
+struct Problem {
+    error: JsonError,
+    /// Whether the problem is the key of an object member rather than its value.
+    on_key: bool,
+}
+
+struct Validator<'a> {
+    pool: &'a DescriptorPool,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+    fn push(&mut self, error: JsonError, on_key: bool) {
+        self.problems.push(Problem { error, on_key });
+    }
+
+    fn report(&mut self, path: &str, message: impl Into<String>, on_key: bool) {
+        let error = JsonError {
+            path: path.to_owned(),
+            message: message.into(),
+        };
+        self.push(error, on_key);
+    }
+
+    fn message(&mut self, descriptor: &MessageDescriptor, value: &Value, path: &str) {
+        let full_name = descriptor.full_name();
+        if full_name == "google.protobuf.Any" {
+            return self.any(value, path);
+        }
+        if json::has_special_form(full_name) {
+            // Special forms are small enough that their first problem is all there is to say.
+            let t = RuntimeType::Message(descriptor.clone());
+            if let Err(error) = json::field_value(&t, value, path, self.pool) {
+                self.push(error, false);
+            }
+            return;
+        }
+
+        match value {
+            Value::Object(object) => self.fields(descriptor, object, path),
+            _ => self.report(path, format!("expected an object for {}", full_name), false),
+        }
+    }
+
+    fn fields(&mut self, descriptor: &MessageDescriptor, object: &Map<String, Value>, path: &str) {
+        let mut oneofs: HashMap<String, &str> = HashMap::new();
+        for (key, item) in object {
+            let item_path = pointer(path, key);
+            let Some(field) = descriptor
+                .fields()
+                .find(|f| f.json_name() == key || f.name() == key)
+            else {
+                let message = format!("unknown field `{}` in {}", key, descriptor.full_name());
+                self.report(&item_path, message, true);
+                continue;
+            };
+            if item.is_null() && !json::accepts_null(&field) {
+                continue;
+            }
+            if let Some(oneof) = field.containing_oneof() {
+                if let Some(other) = oneofs.insert(oneof.name().to_owned(), key) {
+                    let message = format!(
+                        "`{}` and `{}` belong to the same oneof `{}`",
+                        other,
+                        key,
+                        oneof.name()
+                    );
+                    self.report(&item_path, message, true);
+                }
+            }
+
+            match field.runtime_field_type() {
+                RuntimeFieldType::Singular(t) => self.value(&t, item, &item_path),
+                RuntimeFieldType::Repeated(t) => match item {
+                    Value::Array(items) => {
+                        for (i, v) in items.iter().enumerate() {
+                            self.value(&t, v, &pointer(&item_path, &i.to_string()));
+                        }
+                    }
+                    _ => self.report(&item_path, "expected an array", false),
+                },
+                RuntimeFieldType::Map(k, t) => match item {
+                    Value::Object(entries) => {
+                        for (key, v) in entries {
+                            let entry_path = pointer(&item_path, key);
+                            if let Err(error) = json::map_key(&k, key, &entry_path, self.pool) {
+                                self.push(error, true);
+                            }
+                            self.value(&t, v, &entry_path);
+                        }
+                    }
+                    _ => self.report(&item_path, "expected an object", false),
+                },
+            }
+        }
+    }
+
+    fn value(&mut self, t: &RuntimeType, value: &Value, path: &str) {
+        match t {
+            RuntimeType::Message(m)
+                if !value.is_null() || m.full_name() == "google.protobuf.Value" =>
+            {
+                self.message(m, value, path)
+            }
+            t => {
+                if let Err(error) = json::field_value(t, value, path, self.pool) {
+                    self.push(error, false);
+                }
+            }
+        }
+    }
+
+    fn any(&mut self, value: &Value, path: &str) {
+        let Value::Object(object) = value else {
+            return self.report(path, "expected an object with an `@type`", false);
+        };
+        let Some(type_url) = object.get("@type") else {
+            return self.report(path, "missing `@type`", false);
+        };
+        let type_path = pointer(path, "@type");
+        let type_url = match json::expect_str(type_url, &type_path) {
+            Ok(type_url) => type_url,
+            Err(error) => return self.push(error, false),
+        };
+        let Some(descriptor) = json::resolve_type_url(self.pool, type_url) else {
+            return self.report(&type_path, format!("unknown type `{}`", type_url), false);
+        };
+
+        if json::has_special_form(descriptor.full_name()) {
+            match object.get("value") {
+                Some(inner) => self.message(&descriptor, inner, &pointer(path, "value")),
+                None => {
+                    let message = format!("missing `value` for {}", descriptor.full_name());
+                    self.report(path, message, false);
+                }
+            }
+        } else {
+            let mut fields = object.clone();
+            fields.remove("@type");
+            self.fields(&descriptor, &fields, path);
+        }
+    }
+}
+
+/// Where a value is in the text, in UTF-16 code units like Dart strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Span {
+    /// The key of the value, when it is an object member.
+    key: Option<(u32, u32)>,
+    value: (u32, u32),
+}
+
+/// The UTF-16 offset of serde_json's 1-based line and column, the latter counted in bytes.
+fn offset_at(text: &str, line: usize, column: usize) -> u32 {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let mut end = (line_start + column.saturating_sub(1)).min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    text[..end].encode_utf16().count() as u32
+}
+
+/// The span of every value in `text`, valid JSON, by JSON pointer.
+fn spans(text: &str) -> HashMap<String, Span> {
+    let mut scanner = Scanner {
+        chars: text.chars().collect(),
+        pos: 0,
+        offset: 0,
+        spans: HashMap::new(),
+    };
+    scanner.value(String::new(), None);
+
+    scanner.spans
+}
+
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+    /// Position in UTF-16 code units.
+    offset: u32,
+    spans: HashMap<String, Span>,
+}
+
+impl Scanner {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        self.offset += c.len_utf16() as u32;
+        Some(c)
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn value(&mut self, path: String, key: Option<(u32, u32)>) {
+        self.whitespace();
+        let start = self.offset;
+        match self.peek() {
+            Some('{') => {
+                self.bump();
+                loop {
+                    self.whitespace();
+                    match self.peek() {
+                        Some('}') | None => break,
+                        Some(',') => {
+                            self.bump();
+                        }
+                        _ => {
+                            let key_start = self.offset;
+                            let name = self.string();
+                            let key = (key_start, self.offset);
+                            self.whitespace();
+                            self.bump(); // `:`
+                            self.value(pointer(&path, &name), Some(key));
+                        }
+                    }
+                }
+                self.bump();
+            }
+            Some('[') => {
+                self.bump();
+                let mut index = 0;
+                loop {
+                    self.whitespace();
+                    match self.peek() {
+                        Some(']') | None => break,
+                        Some(',') => {
+                            self.bump();
+                        }
+                        _ => {
+                            self.value(pointer(&path, &index.to_string()), None);
+                            index += 1;
+                        }
+                    }
+                }
+                self.bump();
+            }
+            Some('"') => {
+                self.string();
+            }
+            _ => {
+                while self
+                    .peek()
+                    .is_some_and(|c| !(c.is_whitespace() || matches!(c, ',' | '}' | ']')))
+                {
+                    self.bump();
+                }
+            }
+        }
+        let span = Span {
+            key,
+            value: (start, self.offset),
+        };
+        self.spans.insert(path, span);
+    }
+
+    /// A string literal, unescaped.
+    fn string(&mut self) -> String {
+        let mut units = Vec::new();
+        self.bump();
+        while let Some(c) = self.bump() {
+            match c {
+                '"' => break,
+                '\\' => match self.bump() {
+                    Some('u') => {
+                        let digits: String = (0..4).filter_map(|_| self.bump()).collect();
+                        units.push(u16::from_str_radix(&digits, 16).unwrap_or_default());
+                    }
+                    Some(c) => {
+                        let c = match c {
+                            'b' => '\u{8}',
+                            'f' => '\u{c}',
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            c => c,
+                        };
+                        units.extend(c.encode_utf16(&mut [0; 2]).iter());
+                    }
+                    None => break,
+                },
+                c => units.extend(c.encode_utf16(&mut [0; 2]).iter()),
+            }
+        }
+
+        String::from_utf16_lossy(&units)
+    }
+}
+
+#[test]
+fn every_problem_is_located() {
+    let (pool, descriptor) = crate::test_server::echo_message();
+
+    let text = r#"{
+  "text": "café",
+  "nope": true,
+  "count": -1,
+  "mood": "SAD",
+  "name": "a",
+  "code": 2,
+  "at": "yesterday",
+  "elapsed": "1s",
+  "numbers": [1, "x"],
+  "scores": {"aé": "1", "b": 1.5},
+  "labels": {"z": "x"},
+  "child": {"id": "9223372036854775808", "ratio": "NaN", "at": "2023-01-01T00:00:0é"},
+  "detail": {"@type": "type.googleapis.com/echo.v1.EchoMessage", "flag": "yes"}
+}"#;
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let diagnostics: Vec<(String, String, String)> = validate(&descriptor, text, &pool)
+        .into_iter()
+        .map(|d| {
+            let underlined = String::from_utf16(&units[d.start as usize..d.end as usize]);
+            (d.path, d.message, underlined.unwrap())
+        })
+        .collect();
+    let expected = [
+        (
+            "/nope",
+            "unknown field `nope` in echo.v1.EchoMessage",
+            "\"nope\"",
+        ),
+        ("/count", "-1 is out of range for uint32", "-1"),
+        (
+            "/mood",
+            "`SAD` is not a value of enum echo.v1.EchoMessage.Mood",
+            "\"SAD\"",
+        ),
+        (
+            "/code",
+            "`name` and `code` belong to the same oneof `choice`",
+            "\"code\"",
+        ),
+        (
+            "/at",
+            "expected an RFC 3339 timestamp such as \"1972-01-01T10:00:20.021Z\"",
+            "\"yesterday\"",
+        ),
+        ("/numbers/1", "\"x\" is not an integer", "\"x\""),
+        ("/scores/b", "1.5 is not an integer", "1.5"),
+        ("/labels/z", "\"z\" is not an integer", "\"z\""),
+        (
+            "/child/id",
+            "9223372036854775808 is out of range for int64",
+            "\"9223372036854775808\"",
+        ),
+        (
+            "/child/at",
+            "expected an RFC 3339 timestamp such as \"1972-01-01T10:00:20.021Z\"",
+            "\"2023-01-01T00:00:0é\"",
+        ),
+        ("/detail/flag", "expected true or false", "\"yes\""),
+    ];
+    assert_eq!(
+        diagnostics,
+        expected
+            .iter()
+            .map(|(p, m, u)| (p.to_string(), m.to_string(), u.to_string()))
+            .collect::<Vec<_>>()
+    );
+
+    let diagnostics = validate(&descriptor, "{\n  \"text\": }", &pool);
+    assert_eq!((diagnostics[0].start, diagnostics[0].end), (12, 12));
+    assert!(validate(&descriptor, r#"{"text": "ok", "at": null}"#, &pool).is_empty());
+}
@@ -0,0 +1,374 @@
+#![allow(dead_code)]
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::api::{ProtoError, ProtoParser, Workspace};
+use crate::bundled;
+use crate::pool::DescriptorPool;
+use crate::source_info;
+
+/// Resolves proto import paths against an ordered list of include roots.
+#[derive(Debug, Clone)]
+pub struct ImportResolver {
+    roots: Vec<PathBuf>,
+}
+
+impl ImportResolver {
+    /// Roots configured in `workspace`, then the directory of every input not under any of
+    /// them, then the bundled well-known protos.
+    pub fn new(workspace: &Workspace, inputs: &[PathBuf]) -> Result<Self> {
+        let mut roots: Vec<PathBuf> = Vec::new();
+        for root in &workspace.import_roots {
+            let root = normalize(Path::new(root));
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+        for input in inputs {
+            if !roots.iter().any(|r| input.starts_with(r)) {
+                let parent = input
+                    .parent()
+                    .ok_or_else(|| anyhow!("`{}` has no parent directory", input.display()))?;
+                roots.push(parent.to_owned());
+            }
+        }
+        roots.push(normalize(&bundled::include_dir()?));
+
+        Ok(ImportResolver { roots })
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Find the file an `import "..."` statement refers to.
+    pub fn resolve(&self, import: &str) -> Option<PathBuf> {
+        self.roots
+            .iter()
+            .map(|r| r.join(import))
+            .find(|p| p.is_file())
+    }
+
+    /// The import path `input` is known by, i.e. its path relative to the first root holding it.
+    pub fn proto_name(&self, input: &Path) -> Result<String> {
+        self.roots
+            .iter()
+            .find_map(|r| input.strip_prefix(r).ok())
+            .map(|p| {
+                p.components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .ok_or_else(|| anyhow!("`{}` is not under any import root", input.display()))
+    }
+
+    /// Check that `input` and everything it imports exist and are syntactically valid, so
+    /// problems are reported with their exact location instead of a bare parser error.
+    pub fn check_imports(&self, input: &Path) -> Result<(), ProtoError> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![input.to_owned()];
+
+        while let Some(path) = stack.pop() {
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            let file = path.display().to_string();
+            let content = fs::read_to_string(&path).map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => ProtoError::FileNotFound { path: file.clone() },
+                _ => ProtoError::TypeCheck {
+                    file: file.clone(),
+                    message: e.to_string(),
+                },
+            })?;
+            let parsed = protobuf_parse::pure::parse_dependencies(&content).map_err(|e| {
+                ProtoError::Syntax {
+                    file: file.clone(),
+                    line: e.line,
+                    column: e.col,
+                    message: e.error.to_string(),
+                }
+            })?;
+            for import in parsed.dependency {
+                match self.resolve(&import) {
+                    Some(resolved) => stack.push(resolved),
+                    None => {
+                        return Err(ProtoError::UnresolvedImport {
+                            file,
+                            import,
+                            searched: self.roots.iter().map(|r| r.display().to_string()).collect(),
+                        })
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Absolute, symlink-free form of `path` when it exists, so that prefix checks between
+/// roots and inputs agree.
+fn normalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// Loads files one at a time into a shared pool.
+pub struct Loader<'a> {
+    workspace: &'a Workspace,
+    resolver: ImportResolver,
+    inputs: Vec<PathBuf>,
+    pool: DescriptorPool,
+}
+
+impl<'a> Loader<'a> {
+    pub fn new(paths: &[&str], workspace: &'a Workspace) -> Result<Self> {
+        let inputs: Vec<PathBuf> = paths.iter().map(|p| normalize(Path::new(p))).collect();
+        let resolver = ImportResolver::new(workspace, &inputs)?;
+
+        Ok(Loader {
+            workspace,
+            resolver,
+            inputs,
+            pool: DescriptorPool::default(),
+        })
+    }
+
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    pub fn into_pool(self) -> DescriptorPool {
+        self.pool
+    }
+
+    /// Parse the `index`-th input with everything it imports and link the result into the
+    /// pool. Inputs already linked, as imports of earlier ones, aren't parsed again.
+    ///
+    /// Returns the proto name of the input.
+    pub fn load(&mut self, index: usize) -> Result<String, ProtoError> {
+        let input = &self.inputs[index];
+        if !input.is_file() {
+            return Err(ProtoError::FileNotFound {
+                path: input.display().to_string(),
+            });
+        }
+        if let Ok(name) = self.resolver.proto_name(input) {
+            if self.pool.file_by_name(&name).is_some() {
+                return Ok(name);
+            }
+        }
+        self.resolver.check_imports(input)?;
+
+        let type_check = |e: anyhow::Error| ProtoError::TypeCheck {
+            file: input.display().to_string(),
+            message: format!("{:#}", e),
+        };
+        let mut parser = protobuf_parse::Parser::new();
+        match self.workspace.parser {
+            ProtoParser::Pure => parser.pure(),
+            ProtoParser::Protoc => {
+                let protoc = find_protoc().map_err(|message| ProtoError::TypeCheck {
+                    file: input.display().to_string(),
+                    message,
+                })?;
+                parser
+                    .protoc()
+                    .protoc_path(&protoc)
+                    .capture_stderr()
+                    .protoc_extra_args(["--include_source_info"])
+            }
+        };
+        let mut parsed = parser
+            .includes(self.resolver.roots())
+            .input(input)
+            .parse_and_typecheck()
+            .map_err(type_check)?;
+        // Imports shared with earlier inputs are parsed again, but already linked.
+        parsed
+            .file_descriptors
+            .retain(|f| self.pool.file_by_name(f.name()).is_none());
+        for file in &mut parsed.file_descriptors {
+            if file.source_code_info.is_none() {
+                if let Some(source) = self
+                    .resolver
+                    .resolve(file.name())
+                    .and_then(|p| fs::read_to_string(p).ok())
+                {
+                    file.source_code_info = Some(source_info::from_source(&source, file)).into();
+                }
+            }
+        }
+        self.pool
+            .add_files(parsed.file_descriptors)
+            .map_err(type_check)?;
+
+        Ok(parsed.relative_paths[0].to_string())
+    }
+}
+
+/// The `protoc` binary: the one `PROTOC` names, or else the first on `PATH`.
+///
+/// protobuf-parse panics when it can't find one itself.
+fn find_protoc() -> Result<PathBuf, String> {
+    match std::env::var_os("PROTOC") {
+        Some(path) if Path::new(&path).is_file() => Ok(PathBuf::from(path)),
+        Some(path) => Err(format!(
+            "PROTOC is set to `{}`, which doesn't exist",
+            Path::new(&path).display()
+        )),
+        None => which::which("protoc").map_err(|_| {
+            "protoc was not found on PATH: install it, set PROTOC or use the built-in parser"
+                .to_owned()
+        }),
+    }
+}
+
+/// Load every file in `paths` independently into one shared pool.
+///
+/// A file that fails to load doesn't affect the others; the result for each input is its
+/// proto name or what went wrong, in the order of `paths`.
+pub fn load_files(
+    paths: &[&str],
+    workspace: &Workspace,
+) -> Result<(DescriptorPool, Vec<Result<String, ProtoError>>)> {
+    let mut loader = Loader::new(paths, workspace)?;
+    let results = (0..paths.len()).map(|i| loader.load(i)).collect();
+
+    Ok((loader.into_pool(), results))
+}
+
+/// Like [`load_files`], but fail on the first file that doesn't load.
+///
+/// Returns the pool and the proto names (relative to their import root) of `paths`.
+pub fn load_descriptor_pool(
+    paths: &[&str],
+    workspace: &Workspace,
+) -> Result<(DescriptorPool, Vec<String>)> {
+    let (pool, results) = load_files(paths, workspace)?;
+    let names = results.into_iter().collect::<Result<_, _>>()?;
+
+    Ok((pool, names))
+}
+
+#[test]
+fn resolves_imports_relative_to_roots() {
+    let workspace = Workspace {
+        import_roots: vec!["testdata/roots/a".to_owned(), "testdata/roots/b".to_owned()],
+        ..Default::default()
+    };
+    let (pool, names) = load_descriptor_pool(
+        &["testdata/roots/a/company/orders/v1/orders.proto"],
+        &workspace,
+    )
+    .unwrap();
+
+    assert_eq!(names, ["company/orders/v1/orders.proto"]);
+    assert!(pool.message_by_name("company.auth.v1.Principal").is_some());
+}
+
+#[test]
+fn unresolved_import_lists_searched_roots() {
+    let workspace = Workspace {
+        import_roots: vec!["testdata/roots/a".to_owned()],
+        ..Default::default()
+    };
+    let (_, results) = load_files(
+        &["testdata/roots/a/company/orders/v1/orders.proto"],
+        &workspace,
+    )
+    .unwrap();
+
+    match &results[0] {
+        Err(ProtoError::UnresolvedImport {
+            file,
+            import,
+            searched,
+        }) => {
+            assert!(file.ends_with("orders.proto"));
+            assert_eq!(import, "company/auth/v1/auth.proto");
+            let root = normalize(Path::new("testdata/roots/a"));
+            assert_eq!(searched[0], root.display().to_string());
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn failures_are_reported_per_file() {
+    let (pool, results) = load_files(
+        &[
+            "testdata/errors/missing.proto",
+            "testdata/errors/syntax.proto",
+            "testdata/errors/undefined.proto",
+            "testdata/imports/user.proto",
+        ],
+        &Workspace::default(),
+    )
+    .unwrap();
+
+    assert!(matches!(&results[0], Err(ProtoError::FileNotFound { .. })));
+    match &results[1] {
+        Err(ProtoError::Syntax { line, column, .. }) => assert_eq!((*line, *column), (6, 17)),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(matches!(&results[2], Err(ProtoError::TypeCheck { .. })));
+    assert_eq!(results[3].as_deref().unwrap(), "user.proto");
+    assert!(pool.message_by_name("user.User").is_some());
+}
+
+#[test]
+fn missing_protoc_is_reported() {
+    // Nothing else reads PROTOC: protobuf-parse is always given the path.
+    std::env::set_var("PROTOC", "testdata/no-such-protoc");
+    let workspace = Workspace {
+        parser: ProtoParser::Protoc,
+        ..Default::default()
+    };
+    let (_, results) = load_files(&["testdata/imports/user.proto"], &workspace).unwrap();
+    std::env::remove_var("PROTOC");
+
+    match &results[0] {
+        Err(ProtoError::TypeCheck { file, message }) => {
+            assert!(file.ends_with("user.proto"));
+            assert_eq!(
+                message,
+                "PROTOC is set to `testdata/no-such-protoc`, which doesn't exist"
+            );
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn inputs_already_imported_are_not_parsed_again() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("common")).unwrap();
+    for file in ["user.proto", "common/types.proto"] {
+        fs::copy(
+            Path::new("testdata/imports").join(file),
+            dir.path().join(file),
+        )
+        .unwrap();
+    }
+    let user = dir.path().join("user.proto");
+    let types = dir.path().join("common/types.proto");
+    let workspace = Workspace::default();
+    let mut loader = Loader::new(
+        &[user.to_str().unwrap(), types.to_str().unwrap()],
+        &workspace,
+    )
+    .unwrap();
+
+    assert_eq!(loader.load(0).unwrap(), "user.proto");
+    let files = loader.pool().files().len();
+    // Parsing it again would fail now.
+    fs::write(&types, "not a proto").unwrap();
+    assert_eq!(loader.load(1).unwrap(), "common/types.proto");
+    assert_eq!(loader.pool().files().len(), files);
+}